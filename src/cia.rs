@@ -0,0 +1,315 @@
+//! Wrapping of an NCCH partition in a CIA, the format of installable titles,
+//! and reading of the contents of CIAs.
+//!
+//! A CIA contains a certificate chain, a ticket and a title metadata (TMD)
+//! describing the contents, followed by the contents themselves and a meta
+//! section with the icon. Nothing is signed or encrypted, so installing the
+//! CIA requires signature patches. See <https://www.3dbrew.org/wiki/CIA>.
+
+use crate::ncch::{self, align_usize, put_str, put_u32, put_u64, sha256};
+use crate::smdh::SMDH_SIZE;
+
+use std::io::{self, Write};
+
+const HEADER_SIZE: usize = 0x2020;
+const ALIGNMENT: usize = 64;
+
+/// RSA-4096 with SHA-256, used by the root key.
+const SIG_RSA_4096: u32 = 0x0001_0003;
+/// RSA-2048 with SHA-256.
+const SIG_RSA_2048: u32 = 0x0001_0004;
+
+const CA_ISSUER: &str = "Root-CA00000003";
+const TICKET_ISSUER: &str = "Root-CA00000003-XS0000000c";
+const TMD_ISSUER: &str = "Root-CA00000003-CP0000000b";
+
+/// The content index of tickets granting access to all contents, as written by
+/// makerom.
+const CONTENT_INDEX_HEADER: [u32; 11] = [
+    0x0001_0014,
+    0xAC,
+    0x14,
+    0x0001_0014,
+    0,
+    0x28,
+    1,
+    0x84,
+    0x84,
+    0x0003_0000,
+    0,
+];
+
+/// The title and contents of a CIA.
+#[derive(Debug)]
+pub struct Cia<'a> {
+    pub title_id: u64,
+    pub title_version: u16,
+    pub contents: Vec<Content<'a>>,
+    /// The icon in the meta section, if any.
+    pub smdh: Option<&'a [u8]>,
+}
+
+/// A content of a CIA, usually an NCCH partition.
+#[derive(Debug)]
+pub struct Content<'a> {
+    pub id: u32,
+    pub index: u16,
+    pub data: &'a [u8],
+}
+
+/// Write a CIA containing a single NCCH partition.
+pub fn write_cia(
+    mut out: impl Write,
+    title_id: u64,
+    partition: &[u8],
+    smdh: &[u8],
+) -> io::Result<()> {
+    let cert_chain = build_cert_chain();
+    let ticket = build_ticket(title_id);
+    let tmd = build_tmd(title_id, partition);
+    let meta = build_meta(smdh);
+
+    let mut header = vec![0; HEADER_SIZE];
+    put_u32(&mut header, 0x0, HEADER_SIZE as u32);
+    put_u32(&mut header, 0x8, cert_chain.len() as u32);
+    put_u32(&mut header, 0xC, ticket.len() as u32);
+    put_u32(&mut header, 0x10, tmd.len() as u32);
+    put_u32(&mut header, 0x14, meta.len() as u32);
+    put_u64(&mut header, 0x18, partition.len() as u64);
+    header[0x20] = 0x80; // content index 0
+
+    for section in [&header, &cert_chain, &ticket, &tmd, partition, &meta] {
+        out.write_all(section)?;
+        let padding = align_usize(section.len(), ALIGNMENT) - section.len();
+        out.write_all(&[0; ALIGNMENT][..padding])?;
+    }
+
+    Ok(())
+}
+
+impl<'a> Cia<'a> {
+    /// Read the title metadata and the meta section of a CIA, or `None` if
+    /// the data is not one.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let word = |offset: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                data.get(offset..offset + 4)?.try_into().unwrap(),
+            ))
+        };
+        if word(0)? != HEADER_SIZE as u32 {
+            return None;
+        }
+
+        // The sections follow the header in this order, each aligned.
+        let sizes = [
+            HEADER_SIZE,
+            word(0x8)? as usize,
+            word(0xC)? as usize,
+            word(0x10)? as usize,
+            u64::from_le_bytes(data.get(0x18..0x20)?.try_into().unwrap()) as usize,
+            word(0x14)? as usize,
+        ];
+        let mut offset = 0usize;
+        let mut sections = Vec::with_capacity(sizes.len());
+        for size in sizes {
+            sections.push(data.get(offset..offset.checked_add(size)?)?);
+            offset = align_usize(offset + size, ALIGNMENT);
+        }
+        let [_, _, _, tmd, content, meta] = sections[..] else {
+            unreachable!()
+        };
+
+        let tmd = tmd.get(signature_size(tmd)?..)?;
+        let content_count = u16::from_be_bytes(tmd.get(0x9E..0xA0)?.try_into().unwrap());
+        let mut contents = Vec::with_capacity(content_count.into());
+        let mut content_offset = 0usize;
+        for chunk in tmd.get(0xC4 + 0x24 * 64..)?.chunks_exact(0x30) {
+            if contents.len() == usize::from(content_count) {
+                break;
+            }
+            let size = u64::from_be_bytes(chunk[8..16].try_into().unwrap()) as usize;
+            contents.push(Content {
+                id: u32::from_be_bytes(chunk[..4].try_into().unwrap()),
+                index: u16::from_be_bytes(chunk[4..6].try_into().unwrap()),
+                data: content.get(content_offset..content_offset.checked_add(size)?)?,
+            });
+            content_offset = align_usize(content_offset + size, ALIGNMENT);
+        }
+
+        Some(Self {
+            title_id: u64::from_be_bytes(tmd.get(0x4C..0x54)?.try_into().unwrap()),
+            title_version: u16::from_be_bytes(tmd.get(0x9C..0x9E)?.try_into().unwrap()),
+            contents,
+            smdh: meta.get(0x400..0x400 + SMDH_SIZE),
+        })
+    }
+}
+
+/// The size of the signature of a signed structure, with its padding to a
+/// multiple of 64 bytes.
+fn signature_size(data: &[u8]) -> Option<usize> {
+    let sig_size = match u32::from_be_bytes(data.get(..4)?.try_into().unwrap()) {
+        0x0001_0000 | SIG_RSA_4096 => 0x200,
+        0x0001_0001 | SIG_RSA_2048 => 0x100,
+        0x0001_0002 | 0x0001_0005 => 0x3C,
+        _ => return None,
+    };
+    Some(align_usize(4 + sig_size, ALIGNMENT))
+}
+
+/// Start a signed structure with an empty signature of the given type.
+fn signed(sig_type: u32) -> Vec<u8> {
+    let mut data = sig_type.to_be_bytes().to_vec();
+    data.resize(signature_size(&data).unwrap(), 0);
+    data
+}
+
+/// Append a fixed-size string field.
+fn push_str(data: &mut Vec<u8>, value: &str, size: usize) {
+    let start = data.len();
+    data.resize(start + size, 0);
+    put_str(&mut data[start..], value);
+}
+
+/// Build placeholders for the certificates of the CA, ticket and TMD issuers,
+/// with empty signatures and public keys.
+fn build_cert_chain() -> Vec<u8> {
+    let mut chain = Vec::new();
+
+    for (sig_type, issuer, name) in [
+        (SIG_RSA_4096, "Root", "CA00000003"),
+        (SIG_RSA_2048, CA_ISSUER, "XS0000000c"),
+        (SIG_RSA_2048, CA_ISSUER, "CP0000000b"),
+    ] {
+        let mut cert = signed(sig_type);
+        push_str(&mut cert, issuer, 0x40);
+        cert.extend(1u32.to_be_bytes()); // key type: RSA-2048
+        push_str(&mut cert, name, 0x40);
+        cert.extend(0u32.to_be_bytes()); // expiration time
+        cert.resize(cert.len() + 0x100, 0); // modulus
+        cert.extend(0x10001u32.to_be_bytes()); // public exponent
+        cert.resize(cert.len() + 0x34, 0);
+        chain.extend(cert);
+    }
+
+    chain
+}
+
+/// Build a ticket for the title, with an empty title key since the contents
+/// are not encrypted.
+fn build_ticket(title_id: u64) -> Vec<u8> {
+    let mut ticket = signed(SIG_RSA_2048);
+    let start = ticket.len();
+    ticket.resize(start + 0x164, 0);
+
+    let data = &mut ticket[start..];
+    put_str(&mut data[..0x40], TICKET_ISSUER);
+    data[0x7C] = 1; // format version
+    data[0x9C..0xA4].copy_from_slice(&title_id.to_be_bytes());
+
+    for word in CONTENT_INDEX_HEADER {
+        ticket.extend(word.to_be_bytes());
+    }
+    ticket.resize(ticket.len() + 0x80, 0xFF);
+
+    ticket
+}
+
+/// Build the title metadata, listing the partition as the only content.
+fn build_tmd(title_id: u64, partition: &[u8]) -> Vec<u8> {
+    let mut content_chunk = Vec::with_capacity(0x30);
+    content_chunk.extend(0u32.to_be_bytes()); // content ID
+    content_chunk.extend(0u16.to_be_bytes()); // content index
+    content_chunk.extend(0u16.to_be_bytes()); // type: not encrypted
+    content_chunk.extend((partition.len() as u64).to_be_bytes());
+    content_chunk.extend(sha256(partition));
+
+    let mut content_info = vec![0; 0x24 * 64];
+    content_info[2..4].copy_from_slice(&1u16.to_be_bytes()); // content count
+    content_info[4..0x24].copy_from_slice(&sha256(&content_chunk));
+
+    let mut tmd = signed(SIG_RSA_2048);
+    let start = tmd.len();
+    tmd.resize(start + 0xC4, 0);
+
+    let header = &mut tmd[start..];
+    put_str(&mut header[..0x40], TMD_ISSUER);
+    header[0x40] = 1; // format version
+    header[0x4C..0x54].copy_from_slice(&title_id.to_be_bytes());
+    header[0x54..0x58].copy_from_slice(&0x40u32.to_be_bytes()); // title type
+    header[0x9E..0xA0].copy_from_slice(&1u16.to_be_bytes()); // content count
+    header[0xA4..0xC4].copy_from_slice(&sha256(&content_info));
+
+    tmd.extend(content_info);
+    tmd.extend(content_chunk);
+    tmd
+}
+
+/// Build the meta section, holding the dependencies and the icon.
+fn build_meta(smdh: &[u8]) -> Vec<u8> {
+    let mut meta = vec![0; 0x400];
+    for (i, dependency) in ncch::DEPENDENCIES.iter().enumerate() {
+        put_u64(&mut meta, i * 8, *dependency);
+    }
+    put_u32(&mut meta, 0x300, ncch::CORE_VERSION);
+    meta.extend(smdh);
+    meta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn section_sizes() {
+        let partition = vec![0x42; 0x600];
+        let smdh = vec![0x11; 0x36C0];
+        let mut cia = Vec::new();
+        write_cia(&mut cia, 0x0004_0000_0FF3_FF00, &partition, &smdh).unwrap();
+
+        let sizes = [0x0, 0x8, 0xC, 0x10, 0x14].map(|offset| read_u32(&cia, offset));
+        assert_eq!(sizes, [0x2020, 0xA00, 0x350, 0xB34, 0x3AC0]);
+        assert_eq!(cia[0x20], 0x80);
+
+        // Each section starts on a 64-byte boundary.
+        let ticket = 0x2040 + 0xA00;
+        let tmd = ticket + 0x380;
+        let content = tmd + 0xB40;
+        let meta = content + 0x600;
+        assert_eq!(cia.len(), meta + 0x3AC0);
+
+        assert_eq!(
+            &cia[ticket + 0x140 + 0x9C..][..8],
+            &[0, 4, 0, 0, 0x0F, 0xF3, 0xFF, 0]
+        );
+        assert_eq!(
+            &cia[tmd + 0x140 + 0x4C..][..8],
+            &[0, 4, 0, 0, 0x0F, 0xF3, 0xFF, 0]
+        );
+        assert_eq!(&cia[tmd + 0xB04 + 0x10..][..0x20], &sha256(&partition));
+        assert_eq!(&cia[content..meta], &partition[..]);
+        assert_eq!(&cia[meta + 0x400..], &smdh[..]);
+    }
+
+    #[test]
+    fn read_contents() {
+        let partition = vec![0x42; 0x600];
+        let smdh = vec![0x11; 0x36C0];
+        let mut cia = Vec::new();
+        write_cia(&mut cia, 0x0004_0000_0FF3_FF00, &partition, &smdh).unwrap();
+
+        let parsed = Cia::parse(&cia).unwrap();
+        assert_eq!(parsed.title_id, 0x0004_0000_0FF3_FF00);
+        assert_eq!(parsed.title_version, 0);
+        assert_eq!(parsed.contents.len(), 1);
+        assert_eq!(parsed.contents[0].data, &partition[..]);
+        assert_eq!(parsed.smdh, Some(&smdh[..]));
+
+        assert!(Cia::parse(&cia[..0x3000]).is_none());
+        assert!(Cia::parse(b"3DSX").is_none());
+    }
+}
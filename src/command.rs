@@ -1,5 +1,10 @@
+use crate::scaffold::Template;
+use crate::Error;
+
 use clap::{Args, Parser, Subcommand};
 
+use std::path::{Path, PathBuf};
+
 #[derive(Parser, Debug)]
 #[command(name = "cargo", bin_name = "cargo")]
 pub enum Cargo {
@@ -23,17 +28,69 @@ pub struct Input {
 #[command(allow_external_subcommands = true)]
 pub enum CargoCmd {
     /// Builds an executable suitable to run on a 3DS (3dsx).
-    Build(RemainingArgs),
+    Build(Build),
 
     /// Builds an executable and sends it to a device with `3dslink`.
     Run(Run),
 
-    /// Builds a test executable and sends it to a device with `3dslink`.
+    /// Builds test executables and runs each of them on a device with `3dslink`.
     ///
     /// This can be used with `--test` for integration tests, or `--lib` for
-    /// unit tests (which require a custom test runner).
+    /// unit tests (which require a custom test runner). The output of each
+    /// executable is received through the 3dslink server, so the test runner
+    /// must redirect its stdio to it, and a summary of the results is printed
+    /// once all of them have run.
     Test(Test),
 
+    /// Builds an executable and debugs it with GDB, connected to a remote stub.
+    ///
+    /// The stub is e.g. the GDB server of Luma3DS, enabled from the Rosalina
+    /// menu, or that of an emulator. GDB is devkitARM's `arm-none-eabi-gdb`,
+    /// unless set with `gdb` in the user config file.
+    Debug(Debug),
+
+    /// Resolves code addresses to functions and source lines, from the debug
+    /// info of the built executable.
+    ///
+    /// The addresses are given as arguments, or else found in the lines read
+    /// from stdin, e.g. the output of a panic. The executable is built with the
+    /// cargo options given after `--` (e.g. `-- --release --example foo`),
+    /// unless it is given with `--elf`.
+    #[command(name = "addr2line")]
+    Addr2line(Addr2line),
+
+    /// Prints a Luma3DS crash dump, with the functions of the code addresses in
+    /// it.
+    ///
+    /// The addresses are resolved with the built executable that crashed, built
+    /// with the cargo options given after `--`, unless it is given with `--elf`.
+    Crash(Crash),
+
+    /// Prints the header fields, sizes and SMDH of a 3DSX, SMDH or CIA file.
+    Info(Info),
+
+    /// Lists or extracts the files of the RomFS in a 3DSX, CIA or RomFS
+    /// image.
+    Romfs(Romfs),
+
+    /// Creates a new 3DS project in a new directory.
+    ///
+    /// The project is set up to build with `cargo 3ds`: it depends on `ctru-rs`,
+    /// uses the nightly toolchain, and its `[package.metadata.cargo-3ds]` table
+    /// is filled in.
+    New(New),
+
+    /// Creates a new 3DS project in an existing directory.
+    ///
+    /// Files that already exist in the directory are kept.
+    Init(Init),
+
+    /// Checks the environment needed to build and run 3DS executables.
+    ///
+    /// Reports problems with devkitPro, libctru, the Rust toolchain and the
+    /// standard library, with hints on how to fix them.
+    Doctor,
+
     // NOTE: it seems docstring + name for external subcommands are not rendered
     // in help, but we might as well set them here in case a future version of clap
     // does include them in help text.
@@ -60,12 +117,154 @@ pub struct RemainingArgs {
     args: Vec<String>,
 }
 
+#[derive(Parser, Debug)]
+pub struct Build {
+    /// Also build an installable title (cia) next to the 3dsx.
+    ///
+    /// The title ID is made from the `unique_id` in the
+    /// `[package.metadata.cargo-3ds]` table of the manifest, or set directly
+    /// with `title_id`.
+    #[arg(long)]
+    pub cia: bool,
+
+    /// Also build a cartridge image (3ds) next to the 3dsx.
+    ///
+    /// The title ID is set the same way as for `--cia`. The card is configured
+    /// with `media_size` and `card_type` in `[package.metadata.cargo-3ds]`.
+    #[arg(long)]
+    pub cci: bool,
+
+    /// Rebuild when the sources of the workspace or the assets of the
+    /// executables (e.g. the `RomFS` directory) change.
+    #[arg(long)]
+    pub watch: bool,
+
+    // Passthrough cargo options.
+    #[command(flatten)]
+    pub cargo_args: RemainingArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct Debug {
+    /// The `host:port` address of the GDB stub, e.g. `192.168.0.2:4003` for the
+    /// first process debugged with Luma3DS, or `localhost:24689` for Citra.
+    #[arg(long, value_name = "HOST:PORT")]
+    pub remote: String,
+
+    // Passthrough cargo options.
+    #[command(flatten)]
+    pub cargo_args: RemainingArgs,
+}
+
+#[derive(Parser, Debug)]
+pub struct Addr2line {
+    /// The addresses to resolve, in hexadecimal (e.g. `0x00123abc`).
+    #[arg(value_parser = parse_address)]
+    pub addresses: Vec<u64>,
+
+    /// Use this ELF executable instead of building one.
+    #[arg(long)]
+    pub elf: Option<PathBuf>,
+
+    /// Options passed to `cargo build` to build the executable.
+    #[arg(last = true, name = "CARGO_ARGS")]
+    pub cargo_args: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Crash {
+    /// The dump, e.g. `luma/dumps/arm11/crash_dump_00000000.dmp` from the SD card.
+    pub dump: PathBuf,
+
+    /// Use this ELF executable instead of building one.
+    #[arg(long)]
+    pub elf: Option<PathBuf>,
+
+    /// Options passed to `cargo build` to build the executable.
+    #[arg(last = true, name = "CARGO_ARGS")]
+    pub cargo_args: Vec<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Info {
+    /// The 3DSX, SMDH or CIA file.
+    pub file: PathBuf,
+
+    /// Print the information as JSON, e.g. for scripts.
+    #[arg(long)]
+    pub json: bool,
+
+    /// Save the small and large icons as PNG images in this directory.
+    #[arg(long, value_name = "DIR")]
+    pub export_icons: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Romfs {
+    #[command(subcommand)]
+    pub action: RomfsAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RomfsAction {
+    /// Lists the directories and files, with the offsets and sizes of the
+    /// files in the artifact.
+    Ls {
+        /// The 3DSX, CIA or RomFS image.
+        artifact: PathBuf,
+    },
+
+    /// Extracts the directories and files into a directory.
+    Extract {
+        /// The 3DSX, CIA or RomFS image.
+        artifact: PathBuf,
+
+        /// The directory to extract into, created if needed.
+        dir: PathBuf,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct New {
+    /// The directory to create.
+    pub path: PathBuf,
+
+    #[command(flatten)]
+    pub options: NewOptions,
+}
+
+#[derive(Parser, Debug)]
+pub struct Init {
+    /// The directory to create the project in.
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+
+    #[command(flatten)]
+    pub options: NewOptions,
+}
+
+#[derive(Args, Debug)]
+pub struct NewOptions {
+    /// The kind of project to create.
+    #[arg(long, value_enum, default_value_t)]
+    pub template: Template,
+
+    /// Set the package name. Defaults to the directory name.
+    #[arg(long)]
+    pub name: Option<String>,
+}
+
 #[derive(Parser, Debug)]
 pub struct Test {
     /// If set, the built executable will not be sent to the device to run it.
     #[arg(long)]
     pub no_run: bool,
 
+    /// Consider a test executable crashed once it has printed nothing for
+    /// this many seconds, or never with 0.
+    #[arg(long, value_name = "SECS", default_value_t = 120)]
+    pub output_timeout: u64,
+
     // The test command uses a superset of the same arguments as Run.
     #[command(flatten)]
     pub run_args: Run,
@@ -85,8 +284,10 @@ pub struct Run {
     #[arg(long, short = '0')]
     pub argv0: Option<String>,
 
-    /// Start the 3dslink server after sending the executable. Corresponds to
-    /// 3dslink's `--server` argument.
+    /// Start the 3dslink server after sending the executable, printing the
+    /// output of the executable. The server keeps waiting for the executable
+    /// to reconnect until interrupted. Corresponds to 3dslink's `--server`
+    /// argument.
     #[arg(long, short = 's', default_value_t = false)]
     pub server: bool,
 
@@ -96,6 +297,23 @@ pub struct Run {
     #[arg(long)]
     pub retries: Option<usize>,
 
+    /// Run the executable in an emulator instead of sending it to a device,
+    /// and exit with the status of the emulator.
+    ///
+    /// Without a PATH, the emulator is the one set with `emulator` in
+    /// `[package.metadata.cargo-3ds]`, or in the user config file
+    /// (`~/.config/cargo-3ds/config.toml`).
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    pub emulator: Option<Option<PathBuf>>,
+
+    /// Rebuild and run the executable again when the sources of the workspace
+    /// or the assets of the executable (e.g. the `RomFS` directory) change.
+    ///
+    /// With `--server`, the 3dslink server keeps printing the output of each
+    /// run.
+    #[arg(long)]
+    pub watch: bool,
+
     // Passthrough cargo options.
     #[command(flatten)]
     pub cargo_args: RemainingArgs,
@@ -104,33 +322,73 @@ pub struct Run {
 impl CargoCmd {
     /// Whether or not this command should build a 3DSX executable file.
     pub fn should_build_3dsx(&self) -> bool {
-        matches!(self, Self::Build(_) | Self::Run(_) | Self::Test(_))
+        matches!(
+            self,
+            Self::Build(_) | Self::Run(_) | Self::Test(_) | Self::Debug(_)
+        )
+    }
+
+    /// Whether or not this command should also build an installable CIA.
+    pub fn should_build_cia(&self) -> bool {
+        matches!(self, Self::Build(build) if build.cia)
+    }
+
+    /// Whether or not this command should also build a cartridge image.
+    pub fn should_build_cci(&self) -> bool {
+        matches!(self, Self::Build(build) if build.cci)
+    }
+
+    /// Whether or not to rebuild (and run again) when the sources change.
+    pub fn should_watch(&self) -> bool {
+        match self {
+            CargoCmd::Build(build) => build.watch,
+            CargoCmd::Run(run) => run.watch,
+            CargoCmd::Test(test) => test.run_args.watch,
+            _ => false,
+        }
     }
 
     /// Whether or not the resulting executable should be sent to the 3DS with
     /// `3dslink`.
     pub fn should_link_to_device(&self) -> bool {
         match self {
-            CargoCmd::Test(test) => !test.no_run,
-            CargoCmd::Run(_) => true,
+            CargoCmd::Test(test) => !test.no_run && test.run_args.emulator.is_none(),
+            CargoCmd::Run(run) => run.emulator.is_none(),
+            _ => false,
+        }
+    }
+
+    /// Whether or not the resulting executable should be run in an emulator.
+    pub fn should_run_in_emulator(&self) -> bool {
+        match self {
+            CargoCmd::Test(test) => !test.no_run && test.run_args.emulator.is_some(),
+            CargoCmd::Run(run) => run.emulator.is_some(),
             _ => false,
         }
     }
 
     pub const DEFAULT_MESSAGE_FORMAT: &str = "json-render-diagnostics";
 
-    pub fn extract_message_format(&mut self) -> Result<Option<String>, String> {
+    pub fn extract_message_format(&mut self) -> Result<Option<String>, Error> {
         Self::extract_message_format_from_args(match self {
-            CargoCmd::Build(args) => &mut args.args,
+            CargoCmd::Build(build) => &mut build.cargo_args.args,
             CargoCmd::Run(run) => &mut run.cargo_args.args,
             CargoCmd::Test(test) => &mut test.run_args.cargo_args.args,
+            CargoCmd::Debug(debug) => &mut debug.cargo_args.args,
+            CargoCmd::Addr2line(addr2line) => &mut addr2line.cargo_args,
+            CargoCmd::Crash(crash) => &mut crash.cargo_args,
             CargoCmd::Passthrough(args) => args,
+            CargoCmd::New(_)
+            | CargoCmd::Init(_)
+            | CargoCmd::Doctor
+            | CargoCmd::Info(_)
+            | CargoCmd::Romfs(_) => return Ok(None),
         })
     }
 
     fn extract_message_format_from_args(
         cargo_args: &mut Vec<String>,
-    ) -> Result<Option<String>, String> {
+    ) -> Result<Option<String>, Error> {
         // Checks for a position within the args where '--message-format' is located
         if let Some(pos) = cargo_args
             .iter()
@@ -149,13 +407,13 @@ impl CargoCmd {
                 cargo_args.remove(pos)
             };
 
-            // Non-json formats are not supported so the executable exits.
+            // Non-json formats are not supported, since the output is parsed.
             if format.starts_with("json") {
                 Ok(Some(format))
             } else {
-                Err(String::from(
-                    "error: non-JSON `message-format` is not supported",
-                ))
+                Err(Error::Config(String::from(
+                    "non-JSON `message-format` is not supported",
+                )))
             }
         } else {
             Ok(None)
@@ -163,6 +421,11 @@ impl CargoCmd {
     }
 }
 
+fn parse_address(address: &str) -> Result<u64, String> {
+    crate::symbolize::parse_address(address)
+        .ok_or_else(|| format!("`{address}` is not a hexadecimal address"))
+}
+
 impl RemainingArgs {
     /// Get the args to be passed to the executable itself (not `cargo`).
     pub fn cargo_args(&self) -> &[String] {
@@ -184,45 +447,18 @@ impl RemainingArgs {
 }
 
 impl Run {
-    /// Get the args to pass to `3dslink` based on these options.
-    pub fn get_3dslink_args(&self) -> Vec<String> {
-        let mut args = Vec::new();
-
-        if let Some(address) = self.address {
-            args.extend(["--address".to_string(), address.to_string()]);
-        }
-
-        if let Some(argv0) = &self.argv0 {
-            args.extend(["--arg0".to_string(), argv0.clone()]);
-        }
-
-        if let Some(retries) = self.retries {
-            args.extend(["--retries".to_string(), retries.to_string()]);
-        }
-
-        if self.server {
-            args.push("--server".to_string());
-        }
-
-        let exe_args = self.cargo_args.exe_args();
-        if !exe_args.is_empty() {
-            // For some reason 3dslink seems to want 2 instances of `--`, one
-            // in front of all of the args like this...
-            args.extend(["--args".to_string(), "--".to_string()]);
-
-            let mut escaped = false;
-            for arg in exe_args.iter().cloned() {
-                if arg.starts_with('-') && !escaped {
-                    // And one before the first `-` arg that is passed in.
-                    args.extend(["--".to_string(), arg]);
-                    escaped = true;
-                } else {
-                    args.push(arg);
-                }
-            }
-        }
-
-        args
+    /// Get the `argv` to start the executable with: the 0th argument, followed
+    /// by the arguments passed after the second `--`.
+    pub fn get_argv(&self, path_3dsx: &Path) -> Vec<String> {
+        let argv0 = self.argv0.clone().unwrap_or_else(|| {
+            // The same default as 3dslink
+            let name = path_3dsx.file_name().unwrap_or_default();
+            format!("3dslink:/{}", name.to_string_lossy())
+        });
+
+        std::iter::once(argv0)
+            .chain(self.cargo_args.exe_args().iter().cloned())
+            .collect()
     }
 }
 
@@ -259,8 +495,13 @@ mod tests {
         ];
 
         for (args, expected) in CASES {
-            let mut cmd = CargoCmd::Build(RemainingArgs {
-                args: args.iter().map(ToString::to_string).collect(),
+            let mut cmd = CargoCmd::Build(Build {
+                cia: false,
+                cci: false,
+                watch: false,
+                cargo_args: RemainingArgs {
+                    args: args.iter().map(ToString::to_string).collect(),
+                },
             });
 
             assert_eq!(
@@ -268,8 +509,8 @@ mod tests {
                 expected.map(ToString::to_string)
             );
 
-            if let CargoCmd::Build(args) = cmd {
-                assert_eq!(args.args, vec!["--foo", "bar"]);
+            if let CargoCmd::Build(build) = cmd {
+                assert_eq!(build.cargo_args.args, vec!["--foo", "bar"]);
             } else {
                 unreachable!();
             }
@@ -279,8 +520,13 @@ mod tests {
     #[test]
     fn extract_format_err() {
         for args in [&["--message-format=foo"][..], &["--message-format", "foo"]] {
-            let mut cmd = CargoCmd::Build(RemainingArgs {
-                args: args.iter().map(ToString::to_string).collect(),
+            let mut cmd = CargoCmd::Build(Build {
+                cia: false,
+                cci: false,
+                watch: false,
+                cargo_args: RemainingArgs {
+                    args: args.iter().map(ToString::to_string).collect(),
+                },
             });
 
             assert!(cmd.extract_message_format().is_err());
@@ -324,4 +570,52 @@ mod tests {
             assert_eq!(cargo_args.exe_args(), param.expected_exe);
         }
     }
+
+    #[test]
+    fn run_emulator() {
+        let cmd = CargoCmd::Run(Run::parse_from(["run", "--release"]));
+        assert!(cmd.should_link_to_device());
+        assert!(!cmd.should_run_in_emulator());
+
+        let run = Run::parse_from(["run", "--emulator", "--release"]);
+        assert_eq!(run.emulator, Some(None));
+        assert_eq!(run.cargo_args.cargo_args(), ["--release"]);
+
+        let run = Run::parse_from(["run", "--emulator=./emu.sh"]);
+        assert_eq!(run.emulator, Some(Some(PathBuf::from("./emu.sh"))));
+        let cmd = CargoCmd::Run(run);
+        assert!(!cmd.should_link_to_device());
+        assert!(cmd.should_run_in_emulator());
+    }
+
+    #[test]
+    fn addr2line_args() {
+        let Cargo::Input(input) = Cargo::parse_from([
+            "cargo",
+            "3ds",
+            "addr2line",
+            "0x100",
+            "ff",
+            "--",
+            "--release",
+        ]);
+        let CargoCmd::Addr2line(addr2line) = input.cmd else {
+            panic!("not parsed as addr2line: {:?}", input.cmd);
+        };
+        assert_eq!(addr2line.addresses, [0x100, 0xFF]);
+        assert_eq!(addr2line.cargo_args, ["--release"]);
+
+        assert!(Cargo::try_parse_from(["cargo", "3ds", "addr2line", "main"]).is_err());
+    }
+
+    #[test]
+    fn run_argv() {
+        let path = Path::new("target/armv6k-nintendo-3ds/debug/app.3dsx");
+
+        let run = Run::parse_from(["run", "--release", "--", "-v", "foo"]);
+        assert_eq!(run.get_argv(path), ["3dslink:/app.3dsx", "-v", "foo"]);
+
+        let run = Run::parse_from(["run", "--argv0", "sdmc:/app.3dsx"]);
+        assert_eq!(run.get_argv(path), ["sdmc:/app.3dsx"]);
+    }
 }
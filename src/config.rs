@@ -0,0 +1,89 @@
+//! The user config file, for settings that depend on the machine rather than
+//! on the package, e.g. `~/.config/cargo-3ds/config.toml`.
+
+use crate::emulator::EmulatorCommand;
+use crate::Error;
+
+use serde::Deserialize;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct UserConfig {
+    /// Emulator used by `cargo 3ds run --emulator` when the package does not
+    /// set one. Relative paths are relative to the config file.
+    pub emulator: Option<EmulatorCommand>,
+
+    /// GDB used by `cargo 3ds debug`, instead of devkitARM's.
+    pub gdb: Option<PathBuf>,
+}
+
+impl UserConfig {
+    /// The path of the config file: `cargo-3ds/config.toml` in the user
+    /// config directory (`$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`).
+    pub fn path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+
+        Some(config_dir.join("cargo-3ds/config.toml"))
+    }
+
+    /// Load the config file, if there is one.
+    pub fn load() -> Result<Self, Error> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn load_from(path: &Path) -> Result<Self, Error> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(Error::io("read", path, e)),
+        };
+
+        let mut config: Self = toml::from_str(&contents).map_err(|source| Error::ConfigParse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        if let (Some(emulator), Some(dir)) = (&mut config.emulator, path.parent()) {
+            emulator.rebase(dir);
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn load_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+
+        let missing = UserConfig::load_from(&path).unwrap();
+        fs::write(
+            &path,
+            "emulator = [\"scripts/emu.sh\", \"--rom\", \"{3dsx}\"]",
+        )
+        .unwrap();
+        let config = UserConfig::load_from(&path).unwrap();
+        fs::write(&path, "emulator = []").unwrap();
+        let invalid = UserConfig::load_from(&path);
+
+        assert!(missing.emulator.is_none());
+        let emulator = config.emulator.unwrap();
+        assert_eq!(emulator.program, dir.path().join("scripts/emu.sh"));
+        assert_eq!(emulator.args, ["--rom", "{3dsx}"]);
+        assert!(matches!(invalid, Err(Error::ConfigParse { .. })));
+    }
+}
@@ -0,0 +1,484 @@
+//! Parsing and printing of the exception dumps written by Luma3DS, e.g.
+//! `/luma/dumps/arm11/crash_dump_00000000.dmp` on the SD card.
+
+use crate::symbolize::{self, Symbolizer};
+
+use core::fmt;
+use std::io::{self, Write};
+
+const MAGIC: [u32; 2] = [0xDEAD_C0DE, 0xDEAD_CAFE];
+const HEADER_SIZE: usize = 0x28;
+/// The oldest version of the format with the same layout, 1.2.
+const MIN_VERSION: u32 = 1 << 16 | 2;
+
+const REGISTER_NAMES: [&str; 23] = [
+    "R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "R8", "R9", "R10", "R11", "R12", "SP", "LR",
+    "PC", "CPSR", "DFSR", "IFSR", "FAR", "FPEXC", "FPINST", "FPINST2",
+];
+const SP: usize = 13;
+const LR: usize = 14;
+const PC: usize = 15;
+const CPSR: usize = 16;
+const DFSR: usize = 17;
+const IFSR: usize = 18;
+const FAR: usize = 19;
+const FPEXC: usize = 20;
+
+/// The Thumb state bit of CPSR.
+const CPSR_THUMB: u32 = 1 << 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Processor {
+    Arm9,
+    Arm11 { core: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    Fiq,
+    UndefinedInstruction,
+    PrefetchAbort,
+    DataAbort,
+    Unknown(u32),
+}
+
+/// The process running on the ARM11 when the exception happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Process {
+    pub name: String,
+    pub title_id: u64,
+}
+
+#[derive(Debug)]
+pub struct Dump {
+    pub processor: Processor,
+    pub exception: Exception,
+    /// R0 to R15 and CPSR, followed by the fault and VFP registers of ARM11.
+    pub registers: Vec<u32>,
+    /// The code before and at PC.
+    pub code: Vec<u8>,
+    /// The stack from SP.
+    pub stack: Vec<u8>,
+    /// `None` for ARM9 dumps, whose additional data is a memory dump.
+    pub process: Option<Process>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file is not a Luma3DS exception dump.
+    NotADump,
+    UnsupportedVersion {
+        major: u16,
+        minor: u16,
+    },
+    /// The file is shorter than its header says.
+    Truncated,
+}
+
+impl Dump {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let word = |offset: usize| -> Result<u32, Error> {
+            let bytes = data.get(offset..offset + 4).ok_or(Error::Truncated)?;
+            Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+
+        if data.len() < HEADER_SIZE || [word(0)?, word(4)?] != MAGIC {
+            return Err(Error::NotADump);
+        }
+
+        let version = word(0x8)?;
+        if version < MIN_VERSION {
+            return Err(Error::UnsupportedVersion {
+                major: (version >> 16) as u16,
+                minor: version as u16,
+            });
+        }
+
+        let processor = match word(0xC)? {
+            9 => Processor::Arm9,
+            processor => Processor::Arm11 {
+                core: (processor >> 16) as u16,
+            },
+        };
+        let exception = match word(0x10)? {
+            0 => Exception::Fiq,
+            1 => Exception::UndefinedInstruction,
+            2 => Exception::PrefetchAbort,
+            3 => Exception::DataAbort,
+            other => Exception::Unknown(other),
+        };
+
+        let sizes = [word(0x18)?, word(0x1C)?, word(0x20)?, word(0x24)?];
+        let mut sections = Vec::with_capacity(sizes.len());
+        let mut offset = HEADER_SIZE;
+        for size in sizes {
+            let section = data
+                .get(offset..offset + size as usize)
+                .ok_or(Error::Truncated)?;
+            sections.push(section);
+            offset += size as usize;
+        }
+        let [registers, code, stack, additional] = sections[..] else {
+            unreachable!()
+        };
+
+        let registers: Vec<u32> = registers
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        if registers.len() <= CPSR {
+            return Err(Error::Truncated);
+        }
+
+        let process = match processor {
+            Processor::Arm11 { .. } if additional.len() >= 16 => Some(Process {
+                name: String::from_utf8_lossy(&additional[..8])
+                    .trim_end_matches('\0')
+                    .to_string(),
+                title_id: u64::from_le_bytes(additional[8..16].try_into().unwrap()),
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            processor,
+            exception,
+            registers,
+            code: code.to_vec(),
+            stack: stack.to_vec(),
+            process,
+        })
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.registers[PC]
+    }
+
+    pub fn lr(&self) -> u32 {
+        self.registers[LR]
+    }
+
+    pub fn sp(&self) -> u32 {
+        self.registers[SP]
+    }
+
+    fn thumb(&self) -> bool {
+        self.registers[CPSR] & CPSR_THUMB != 0
+    }
+
+    fn register(&self, index: usize) -> Option<u32> {
+        self.registers.get(index).copied()
+    }
+
+    /// Why the exception was raised, when it is not a plain fault.
+    fn cause(&self) -> Option<&'static str> {
+        match self.exception {
+            Exception::PrefetchAbort => {
+                // Breakpoints and `svcBreak` are reported as prefetch aborts.
+                let instruction = if self.thumb() {
+                    let bytes = self.code.get(self.code.len().checked_sub(2)?..)?;
+                    u32::from(u16::from_le_bytes(bytes.try_into().unwrap()))
+                } else {
+                    let bytes = self.code.get(self.code.len().checked_sub(4)?..)?;
+                    u32::from_le_bytes(bytes.try_into().unwrap())
+                };
+                match instruction {
+                    0xE12F_FF7E => Some("kernel panic"),
+                    0xEF00_003C | 0xDF3C => Some(match self.registers[0] {
+                        0 => "svcBreak: panic",
+                        1 => "svcBreak: assertion failed",
+                        2 => "svcBreak: user-related",
+                        _ => "svcBreak",
+                    }),
+                    _ => None,
+                }
+            }
+            _ if matches!(self.processor, Processor::Arm11 { .. })
+                && self
+                    .register(FPEXC)
+                    .is_some_and(|fpexc| fpexc & 1 << 31 != 0) =>
+            {
+                Some("VFP exception")
+            }
+            _ => None,
+        }
+    }
+
+    /// The fault status register of aborts.
+    fn fault_status(&self) -> Option<u32> {
+        match (self.processor, self.exception) {
+            (Processor::Arm11 { .. }, Exception::PrefetchAbort) => self.register(IFSR),
+            (Processor::Arm11 { .. }, Exception::DataAbort) => self.register(DFSR),
+            _ => None,
+        }
+    }
+
+    /// Write a readable report of the dump, with the functions of the code
+    /// addresses in it if an executable is given.
+    pub fn write_report(
+        &self,
+        out: &mut dyn Write,
+        symbolizer: Option<&Symbolizer>,
+    ) -> io::Result<()> {
+        writeln!(out, "Processor:        {}", self.processor)?;
+        write!(out, "Exception type:   {}", self.exception)?;
+        match self.cause() {
+            Some(cause) => writeln!(out, " ({cause})")?,
+            None => writeln!(out)?,
+        }
+        if let Some(fsr) = self.fault_status() {
+            writeln!(out, "Fault status:     {}", fault_status(fsr))?;
+        }
+        if self.exception == Exception::DataAbort {
+            if let (Some(far), Some(dfsr)) = (self.register(FAR), self.register(DFSR)) {
+                let access = if dfsr & 1 << 11 != 0 { "write" } else { "read" };
+                writeln!(out, "Faulting address: {far:#010x} ({access})")?;
+            }
+        }
+        if let Some(process) = &self.process {
+            writeln!(
+                out,
+                "Current process:  {} ({:016x})",
+                process.name, process.title_id
+            )?;
+        }
+
+        writeln!(out, "\nRegister dump:\n")?;
+        for (i, pair) in self.registers.chunks(2).enumerate() {
+            if i * 2 == CPSR {
+                writeln!(out)?;
+            }
+            for (j, value) in pair.iter().enumerate() {
+                let name = REGISTER_NAMES.get(i * 2 + j).unwrap_or(&"?");
+                let separator = if j == 0 { "" } else { "    " };
+                write!(out, "{separator}{name:<8}{value:08x}")?;
+            }
+            writeln!(out)?;
+        }
+
+        if let Some(symbolizer) = symbolizer {
+            writeln!(out)?;
+            for (name, address) in [("PC", self.pc()), ("LR", self.lr())] {
+                let frames = frames(symbolizer, address);
+                symbolize::write_frames(out, address.into(), &frames, &format!("{name}  "))?;
+            }
+        }
+
+        writeln!(out, "\nCode dump:\n")?;
+        let width = if self.thumb() { 2 } else { 4 };
+        let start = self
+            .pc()
+            .wrapping_sub(self.code.len() as u32)
+            .wrapping_add(width as u32);
+        for (i, instruction) in self.code.chunks_exact(width).enumerate() {
+            let address = start.wrapping_add((i * width) as u32);
+            let marker = if address == self.pc() { "  <- PC" } else { "" };
+            match instruction {
+                [a, b] => writeln!(
+                    out,
+                    "{address:08x}:  {:04x}{marker}",
+                    u16::from_le_bytes([*a, *b])
+                )?,
+                _ => writeln!(
+                    out,
+                    "{address:08x}:  {:08x}{marker}",
+                    u32::from_le_bytes(instruction.try_into().unwrap())
+                )?,
+            }
+        }
+
+        writeln!(out, "\nStack dump:\n")?;
+        let words = self.stack_words();
+        for line in words.chunks(4) {
+            write!(out, "{:08x}:", line[0].0)?;
+            for (_, word) in line {
+                write!(out, "  {word:08x}")?;
+            }
+            writeln!(out)?;
+        }
+
+        if let Some(symbolizer) = symbolizer {
+            // Code addresses on the stack are mostly return addresses, which
+            // hint at the calls leading to the exception.
+            writeln!(out, "\nCode addresses on the stack:\n")?;
+            for (address, word) in words {
+                if !symbolizer.is_code(word.into()) {
+                    continue;
+                }
+                let frames = frames(symbolizer, word);
+                let indent = format!("sp+{:#06x}  ", address.wrapping_sub(self.sp()));
+                symbolize::write_frames(out, word.into(), &frames, &indent)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The words of the stack dump, with their addresses.
+    fn stack_words(&self) -> Vec<(u32, u32)> {
+        self.stack
+            .chunks_exact(4)
+            .enumerate()
+            .map(|(i, word)| {
+                let address = self.sp().wrapping_add(i as u32 * 4);
+                (address, u32::from_le_bytes(word.try_into().unwrap()))
+            })
+            .collect()
+    }
+}
+
+/// The frames of an address, or none if the debug info is invalid there: the
+/// rest of the report is still worth printing.
+fn frames(symbolizer: &Symbolizer, address: u32) -> Vec<symbolize::Frame> {
+    symbolizer.frames(address.into()).unwrap_or_default()
+}
+
+/// The source of a fault, from the status register of an abort.
+fn fault_status(fsr: u32) -> &'static str {
+    // The fifth bit of the status is bit 10 of the register.
+    match (fsr >> 6) & 0x10 | fsr & 0xF {
+        0b00001 => "alignment",
+        0b00100 => "instruction cache maintenance operation fault",
+        0b01100 => "external abort on translation (first level)",
+        0b01110 => "external abort on translation (second level)",
+        0b00101 => "translation (section)",
+        0b00111 => "translation (page)",
+        0b00011 => "access bit (section)",
+        0b00110 => "access bit (page)",
+        0b01001 => "domain (section)",
+        0b01011 => "domain (page)",
+        0b01101 => "permission (section)",
+        0b01111 => "permission (page)",
+        0b01000 => "precise external abort",
+        0b10110 => "imprecise external abort",
+        0b00010 => "debug event",
+        _ => "unknown",
+    }
+}
+
+impl fmt::Display for Processor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Arm9 => write!(f, "ARM9"),
+            Self::Arm11 { core } => write!(f, "ARM11 (core {core})"),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Fiq => write!(f, "FIQ"),
+            Self::UndefinedInstruction => write!(f, "undefined instruction"),
+            Self::PrefetchAbort => write!(f, "prefetch abort"),
+            Self::DataAbort => write!(f, "data abort"),
+            Self::Unknown(kind) => write!(f, "unknown ({kind})"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotADump => write!(f, "not a Luma3DS exception dump"),
+            Self::UnsupportedVersion { major, minor } => write!(
+                f,
+                "dump format version {major}.{minor} is not supported, only 1.2 and later"
+            ),
+            Self::Truncated => write!(f, "the dump is truncated"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A dump of a data abort in ARM code, writing to a null pointer.
+    fn data_abort() -> Vec<u8> {
+        let mut registers = [0u32; 23];
+        registers[SP] = 0x0FFF_FF00;
+        registers[LR] = 0x0010_0200;
+        registers[PC] = 0x0010_0108;
+        registers[CPSR] = 0x10;
+        registers[DFSR] = 1 << 11 | 0b0101;
+        let code = [0xE320_F000u32, 0xE580_0000];
+        let stack = [0x0010_0200u32, 0x1234];
+        let mut additional = b"app\0\0\0\0\0".to_vec();
+        additional.extend(0x0004_0000_0FF3_FF00u64.to_le_bytes());
+
+        let words =
+            |words: &[u32]| -> Vec<u8> { words.iter().flat_map(|w| w.to_le_bytes()).collect() };
+        let (registers, code, stack) = (words(&registers), words(&code), words(&stack));
+
+        let mut dump = words(&[
+            MAGIC[0],
+            MAGIC[1],
+            1 << 16 | 2,
+            1 << 16 | 11,
+            3,
+            0,
+            registers.len() as u32,
+            code.len() as u32,
+            stack.len() as u32,
+            additional.len() as u32,
+        ]);
+        for section in [registers, code, stack, additional] {
+            dump.extend(section);
+        }
+        dump
+    }
+
+    #[test]
+    fn parse() {
+        let dump = Dump::parse(&data_abort()).unwrap();
+        assert_eq!(dump.processor, Processor::Arm11 { core: 1 });
+        assert_eq!(dump.exception, Exception::DataAbort);
+        assert_eq!(dump.pc(), 0x0010_0108);
+        assert_eq!(
+            dump.process,
+            Some(Process {
+                name: String::from("app"),
+                title_id: 0x0004_0000_0FF3_FF00
+            })
+        );
+        assert_eq!(
+            dump.stack_words(),
+            [(0x0FFF_FF00, 0x0010_0200), (0x0FFF_FF04, 0x1234)]
+        );
+
+        assert!(matches!(Dump::parse(b"not a dump"), Err(Error::NotADump)));
+        let mut truncated = data_abort();
+        truncated.truncate(0x40);
+        assert!(matches!(Dump::parse(&truncated), Err(Error::Truncated)));
+    }
+
+    #[test]
+    fn report() {
+        let dump = Dump::parse(&data_abort()).unwrap();
+        let mut report = Vec::new();
+        dump.write_report(&mut report, None).unwrap();
+        let report = String::from_utf8(report).unwrap();
+
+        assert!(report.contains("Exception type:   data abort\n"));
+        assert!(report.contains("Fault status:     translation (section)\n"));
+        assert!(report.contains("Faulting address: 0x00000000 (write)\n"));
+        assert!(report.contains("Current process:  app (000400000ff3ff00)\n"));
+        assert!(report.contains("LR      00100200    PC      00100108\n"));
+        assert!(report.contains("00100108:  e5800000  <- PC\n"));
+        assert!(report.contains("0fffff00:  00100200  00001234\n"));
+    }
+
+    #[test]
+    fn svc_break() {
+        let mut data = data_abort();
+        // Prefetch abort, at a `svc 0x3C` with R0 = 0.
+        data[0x10] = 2;
+        data[HEADER_SIZE + 23 * 4 + 4..][..4].copy_from_slice(&0xEF00_003Cu32.to_le_bytes());
+
+        let dump = Dump::parse(&data).unwrap();
+        assert_eq!(dump.cause(), Some("svcBreak: panic"));
+    }
+}
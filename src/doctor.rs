@@ -0,0 +1,238 @@
+//! Diagnostics of the environment needed to build 3DS executables, for
+//! `cargo 3ds doctor`.
+
+use crate::{check_rust_version, find_sysroot, libctru_lib_dir, prebuilt_std_dir};
+
+use core::fmt;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// External tools that some projects still use alongside cargo-3ds.
+const TOOLS: [&str; 3] = ["smdhtool", "3dsxtool", "3dslink"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Not required by cargo-3ds, but worth knowing about.
+    Warning,
+    /// Building will fail.
+    Error,
+}
+
+/// The result of checking one requirement.
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub message: String,
+    /// How to fix the problem, if any.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        status: Status,
+        message: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            status,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Check everything needed to build and run 3DS executables.
+pub fn run_checks() -> Vec<Check> {
+    let devkitpro = env::var_os("DEVKITPRO");
+    let mut checks = vec![
+        check_env_dir(
+            "DEVKITPRO",
+            devkitpro.clone(),
+            "install devkitPro (see https://devkitpro.org/wiki/Getting_Started) \
+            and set DEVKITPRO to its path, e.g. /opt/devkitpro",
+        ),
+        check_env_dir(
+            "DEVKITARM",
+            env::var_os("DEVKITARM"),
+            "install devkitARM with `dkp-pacman -S 3ds-dev` and set DEVKITARM to \
+            $DEVKITPRO/devkitARM",
+        ),
+    ];
+
+    if let Some(devkitpro) = &devkitpro {
+        checks.push(check_libctru(Path::new(devkitpro)));
+    }
+
+    checks.push(match check_rust_version() {
+        Ok(()) => Check::ok("rustc", "nightly version is recent enough"),
+        Err(e) => {
+            // The second line of toolchain errors says how to fix them.
+            let message = e.to_string();
+            let (message, hint) = message
+                .split_once('\n')
+                .unwrap_or((&message, "use a recent nightly toolchain"));
+            Check::problem("rustc", Status::Error, message, hint)
+        }
+    });
+
+    match find_sysroot() {
+        Ok(sysroot) => {
+            checks.push(Check::ok("sysroot", sysroot.display().to_string()));
+            checks.push(check_std(&sysroot));
+        }
+        Err(e) => checks.push(Check::problem(
+            "sysroot",
+            Status::Error,
+            e.to_string(),
+            "make sure `rustc` is installed and in PATH, or set RUSTC or SYSROOT",
+        )),
+    }
+
+    let mut search_path: Vec<PathBuf> = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default();
+    if let Some(devkitpro) = &devkitpro {
+        search_path.push(Path::new(devkitpro).join("tools/bin"));
+    }
+    checks.extend(TOOLS.iter().map(|tool| check_tool(tool, &search_path)));
+
+    checks
+}
+
+/// Check that an environment variable is set to an existing directory.
+fn check_env_dir(name: &'static str, value: Option<OsString>, hint: &str) -> Check {
+    match value {
+        None => Check::problem(name, Status::Error, "not set", hint),
+        Some(value) if !Path::new(&value).is_dir() => Check::problem(
+            name,
+            Status::Error,
+            format!("{} is not a directory", Path::new(&value).display()),
+            hint,
+        ),
+        Some(value) => Check::ok(name, Path::new(&value).display().to_string()),
+    }
+}
+
+/// Check that libctru is installed where the build links it from.
+fn check_libctru(devkitpro: &Path) -> Check {
+    let lib_dir = libctru_lib_dir(devkitpro);
+    if lib_dir.join("libctru.a").is_file() {
+        Check::ok("libctru", lib_dir.display().to_string())
+    } else {
+        Check::problem(
+            "libctru",
+            Status::Error,
+            format!("libctru.a not found in {}", lib_dir.display()),
+            "install libctru with `dkp-pacman -S 3ds-dev`",
+        )
+    }
+}
+
+/// Check that the standard library can be used: either it is pre-built, or
+/// its sources are installed for `build-std`.
+fn check_std(sysroot: &Path) -> Check {
+    let rust_src = sysroot.join("lib/rustlib/src/rust/library");
+
+    if prebuilt_std_dir(sysroot).exists() {
+        Check::ok("std", "pre-built for armv6k-nintendo-3ds")
+    } else if rust_src.is_dir() {
+        Check::ok("std", "built with build-std from rust-src")
+    } else {
+        Check::problem(
+            "std",
+            Status::Error,
+            format!(
+                "no pre-built std, and rust-src is not installed in {}",
+                sysroot.display()
+            ),
+            "run `rustup component add rust-src`",
+        )
+    }
+}
+
+/// Look for a tool in the given directories.
+fn check_tool(tool: &'static str, search_path: &[PathBuf]) -> Check {
+    let found = search_path
+        .iter()
+        .map(|dir| dir.join(tool).with_extension(env::consts::EXE_EXTENSION))
+        .find(|path| path.is_file());
+
+    match found {
+        Some(path) => Check::ok(tool, path.display().to_string()),
+        None => Check::problem(
+            tool,
+            Status::Warning,
+            "not found",
+            "cargo-3ds does not need it, but custom build steps may: install it \
+            with `dkp-pacman -S 3ds-tools`",
+        ),
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        };
+        write!(f, "[{status}] {}: {}", self.name, self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n    hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    fn env_dirs() {
+        let check = check_env_dir("DEVKITPRO", None, "install it");
+        assert_eq!(check.status, Status::Error);
+        assert_eq!(
+            check.to_string(),
+            "[error] DEVKITPRO: not set\n    hint: install it"
+        );
+
+        let dir = env::temp_dir();
+        let check = check_env_dir("DEVKITPRO", Some(dir.clone().into()), "install it");
+        assert_eq!(check.status, Status::Ok);
+        assert_eq!(check.message, dir.display().to_string());
+    }
+
+    #[test]
+    fn std_sources() {
+        let temp = tempfile::tempdir().unwrap();
+        let sysroot = temp.path();
+        let missing = check_std(sysroot);
+
+        fs::create_dir_all(sysroot.join("lib/rustlib/src/rust/library")).unwrap();
+        let rust_src = check_std(sysroot);
+
+        fs::create_dir_all(prebuilt_std_dir(sysroot)).unwrap();
+        let prebuilt = check_std(sysroot);
+
+        assert_eq!(missing.status, Status::Error);
+        assert_eq!(rust_src.status, Status::Ok);
+        assert_eq!(prebuilt.message, "pre-built for armv6k-nintendo-3ds");
+    }
+}
@@ -0,0 +1,226 @@
+//! A minimal reader for the 32-bit little-endian ARM ELF executables built for
+//! the 3DS, covering just what is needed to repackage them.
+
+use core::fmt;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+
+pub const SHF_ALLOC: u32 = 0x2;
+
+const ET_EXEC: u16 = 2;
+const EM_ARM: u16 = 40;
+
+/// A parsed ELF executable, borrowing from the file contents.
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: u32,
+    pub segments: Vec<Segment<'a>>,
+    pub sections: Vec<Section>,
+}
+
+/// A program header, together with the file contents it maps.
+pub struct Segment<'a> {
+    pub kind: u32,
+    pub flags: u32,
+    pub vaddr: u32,
+    pub mem_size: u32,
+    pub data: &'a [u8],
+}
+
+/// A section header.
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub info: u32,
+}
+
+/// An entry of a `SHT_REL` or `SHT_RELA` section. Addends are not needed
+/// since the relocations of a linked executable have already been applied.
+pub struct Relocation {
+    pub offset: u32,
+    pub kind: u32,
+}
+
+#[derive(Debug)]
+pub struct ParseError(String);
+
+impl<'a> Elf<'a> {
+    /// Parse the headers of an ARM executable.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ParseError> {
+        if data.get(..4) != Some(b"\x7FELF") {
+            return Err(ParseError::new("not an ELF file"));
+        }
+        if data.get(4..6) != Some(&[1, 1]) {
+            return Err(ParseError::new("not a 32-bit little-endian ELF file"));
+        }
+
+        let reader = Reader(data);
+        if reader.u16(0x10)? != ET_EXEC {
+            return Err(ParseError::new("not an executable"));
+        }
+        if reader.u16(0x12)? != EM_ARM {
+            return Err(ParseError::new("not an ARM executable"));
+        }
+
+        let entry = reader.u32(0x18)?;
+        let phoff = reader.u32(0x1C)? as usize;
+        let shoff = reader.u32(0x20)? as usize;
+        let phentsize = reader.u16(0x2A)? as usize;
+        let phnum = reader.u16(0x2C)? as usize;
+        let shentsize = reader.u16(0x2E)? as usize;
+        let shnum = reader.u16(0x30)? as usize;
+        let shstrndx = reader.u16(0x32)? as usize;
+
+        let segments = (0..phnum)
+            .map(|i| {
+                let header = phoff + i * phentsize;
+                let offset = reader.u32(header + 0x4)? as usize;
+                let file_size = reader.u32(header + 0x10)?;
+
+                Ok(Segment {
+                    kind: reader.u32(header)?,
+                    flags: reader.u32(header + 0x18)?,
+                    vaddr: reader.u32(header + 0x8)?,
+                    mem_size: reader.u32(header + 0x14)?,
+                    data: reader.bytes(offset, file_size)?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut name_offsets = Vec::with_capacity(shnum);
+        let mut sections = (0..shnum)
+            .map(|i| {
+                let header = shoff + i * shentsize;
+                name_offsets.push(reader.u32(header)?);
+
+                Ok(Section {
+                    name: String::new(),
+                    kind: reader.u32(header + 0x4)?,
+                    flags: reader.u32(header + 0x8)?,
+                    addr: reader.u32(header + 0xC)?,
+                    offset: reader.u32(header + 0x10)?,
+                    size: reader.u32(header + 0x14)?,
+                    info: reader.u32(header + 0x1C)?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(strtab) = sections.get(shstrndx) {
+            let strtab = reader.bytes(strtab.offset as usize, strtab.size)?;
+            for (section, name_offset) in sections.iter_mut().zip(name_offsets) {
+                let name = strtab
+                    .get(name_offset as usize..)
+                    .and_then(|name| name.split(|&b| b == 0).next())
+                    .ok_or(ParseError::new("section name out of bounds"))?;
+                section.name = String::from_utf8_lossy(name).into_owned();
+            }
+        }
+
+        Ok(Self {
+            data,
+            entry,
+            segments,
+            sections,
+        })
+    }
+
+    /// The loadable code (`R+X`), read-only data (`R`) and data (`R+W`)
+    /// segments, in this order. Each of them may appear at most once.
+    pub fn program_segments(&self) -> Result<[Option<&Segment<'a>>; 3], ParseError> {
+        let mut segments = [None; 3];
+
+        for segment in &self.segments {
+            if segment.kind != PT_LOAD || segment.mem_size == 0 {
+                continue;
+            }
+
+            let index = match segment.flags & (PF_R | PF_W | PF_X) {
+                flags if flags == PF_R | PF_X => 0,
+                PF_R => 1,
+                flags if flags == PF_R | PF_W => 2,
+                flags => {
+                    return Err(ParseError::new(format!(
+                        "unexpected flags {flags:#x} for segment at {:#010x}",
+                        segment.vaddr
+                    )))
+                }
+            };
+
+            if segments[index].replace(segment).is_some() {
+                return Err(ParseError::new(format!(
+                    "more than one {} segment",
+                    ["code", "rodata", "data"][index]
+                )));
+            }
+        }
+
+        Ok(segments)
+    }
+
+    /// Read the entries of a `SHT_REL` or `SHT_RELA` section.
+    pub fn relocations(&self, section: &Section) -> Result<Vec<Relocation>, ParseError> {
+        let entry_size = match section.kind {
+            SHT_REL => 8,
+            SHT_RELA => 12,
+            _ => return Ok(Vec::new()),
+        };
+        let data = Reader(self.data).bytes(section.offset as usize, section.size)?;
+
+        data.chunks_exact(entry_size)
+            .map(|entry| {
+                let entry = Reader(entry);
+                Ok(Relocation {
+                    offset: entry.u32(0)?,
+                    kind: entry.u32(4)? & 0xFF,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ParseError {
+    fn new(msg: impl Into<String>) -> Self {
+        Self(msg.into())
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid ELF file: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: usize, len: u32) -> Result<&'a [u8], ParseError> {
+        offset
+            .checked_add(len as usize)
+            .and_then(|end| self.0.get(offset..end))
+            .ok_or(ParseError::new("unexpected end of file"))
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, ParseError> {
+        let bytes = self.bytes(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, ParseError> {
+        let bytes = self.bytes(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
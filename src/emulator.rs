@@ -0,0 +1,120 @@
+//! Running executables in an emulator instead of on a device.
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Placeholder for the path of the executable in the arguments of an emulator.
+const PATH_PLACEHOLDER: &str = "{3dsx}";
+
+/// The command line of an emulator, written either as the program alone, or
+/// as a list of the program and its arguments. The path of the executable
+/// replaces `{3dsx}` in the arguments, or is appended to them.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(try_from = "EmulatorRepr")]
+pub struct EmulatorCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+/// The forms the `emulator` setting can be written in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EmulatorRepr {
+    Program(PathBuf),
+    CommandLine(Vec<String>),
+}
+
+impl EmulatorCommand {
+    pub fn new(program: PathBuf) -> Self {
+        Self {
+            program,
+            args: Vec::new(),
+        }
+    }
+
+    /// Make a relative path to the program relative to `base` instead. Bare
+    /// program names (e.g. `lime3ds`) are left to be looked up in `PATH`.
+    pub fn rebase(&mut self, base: &Path) {
+        if self.program.components().count() > 1 {
+            self.program = base.join(&self.program);
+        }
+    }
+
+    /// The command running the given executable.
+    pub fn command(&self, path_3dsx: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+
+        if self.args.iter().any(|arg| arg.contains(PATH_PLACEHOLDER)) {
+            let path = path_3dsx.to_string_lossy();
+            command.args(
+                self.args
+                    .iter()
+                    .map(|arg| arg.replace(PATH_PLACEHOLDER, &path)),
+            );
+        } else {
+            command.args(&self.args).arg(path_3dsx);
+        }
+
+        command
+    }
+}
+
+impl TryFrom<EmulatorRepr> for EmulatorCommand {
+    type Error = &'static str;
+
+    fn try_from(repr: EmulatorRepr) -> Result<Self, Self::Error> {
+        match repr {
+            EmulatorRepr::Program(program) => Ok(Self::new(program)),
+            EmulatorRepr::CommandLine(mut args) => {
+                if args.is_empty() {
+                    return Err("the emulator command line must not be empty");
+                }
+                let program = args.remove(0).into();
+                Ok(Self { program, args })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::OsStr;
+
+    #[test]
+    fn command_line() {
+        let path = Path::new("target/armv6k-nintendo-3ds/debug/app.3dsx");
+
+        let emulator = EmulatorCommand::new(PathBuf::from("lime3ds"));
+        let command = emulator.command(path);
+        assert_eq!(command.get_program(), "lime3ds");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), [path.as_os_str()]);
+
+        let emulator = EmulatorCommand {
+            program: PathBuf::from("citra"),
+            args: vec![String::from("--rom={3dsx}"), String::from("-f")],
+        };
+        let args: Vec<_> = emulator
+            .command(path)
+            .get_args()
+            .map(OsStr::to_owned)
+            .collect();
+        assert_eq!(
+            args,
+            ["--rom=target/armv6k-nintendo-3ds/debug/app.3dsx", "-f"]
+        );
+    }
+
+    #[test]
+    fn rebase_program() {
+        let mut script = EmulatorCommand::new(PathBuf::from("scripts/emu.sh"));
+        script.rebase(Path::new("/project"));
+        assert_eq!(script.program, Path::new("/project/scripts/emu.sh"));
+
+        let mut installed = EmulatorCommand::new(PathBuf::from("lime3ds"));
+        installed.rebase(Path::new("/project"));
+        assert_eq!(installed.program, Path::new("lime3ds"));
+    }
+}
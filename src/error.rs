@@ -0,0 +1,195 @@
+//! The errors that can happen while building or running 3DS executables.
+
+use crate::{crash, ncch, symbolize, threedsx};
+
+use core::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The Rust toolchain or devkitPro setup does not meet the requirements of
+    /// cargo-3ds.
+    Toolchain(String),
+    /// `cargo metadata` failed.
+    Metadata(cargo_metadata::Error),
+    /// The cargo-3ds settings of a manifest are invalid.
+    ManifestParse {
+        path: PathBuf,
+        table: &'static str,
+        source: serde_json::Error,
+    },
+    /// The user config file is invalid.
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// An external tool could not be started.
+    ToolNotFound { tool: String, source: io::Error },
+    /// An external tool did not complete successfully.
+    ToolFailed { tool: String, message: String },
+    /// An external tool that reports its own errors, such as cargo or an
+    /// emulator, exited unsuccessfully. Its exit status is forwarded.
+    Exited { tool: String, status: ExitStatus },
+    /// The build did not produce any executable.
+    NoExecutable,
+    /// The settings of the package do not allow building the requested output.
+    Config(String),
+    /// A file or directory could not be read or written.
+    Io {
+        operation: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The icon could not be loaded.
+    Icon {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    /// The executable could not be converted to a 3dsx.
+    ThreeDsx {
+        path: PathBuf,
+        source: threedsx::Error,
+    },
+    /// The NCCH partition of installable builds could not be built.
+    Ncch { path: PathBuf, source: ncch::Error },
+    /// The debug info of an executable could not be read.
+    Symbolize {
+        path: PathBuf,
+        source: symbolize::Error,
+    },
+    /// A file given to inspect is not in the expected format.
+    InvalidFile {
+        path: PathBuf,
+        expected: &'static str,
+    },
+    /// A crash dump could not be parsed.
+    CrashDump { path: PathBuf, source: crash::Error },
+    /// Sending an executable to the 3DS or receiving its output failed.
+    Link { context: String, source: io::Error },
+    /// Some tests failed or did not report their results.
+    TestsFailed,
+    /// Changes to the sources could not be watched.
+    Watch(notify::Error),
+}
+
+impl Error {
+    /// An error for a failed file operation, e.g. `Error::io("read", path, err)`.
+    pub fn io(operation: &'static str, path: &Path, source: io::Error) -> Self {
+        Self::Io {
+            operation,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl From<cargo_metadata::Error> for Error {
+    fn from(err: cargo_metadata::Error) -> Self {
+        Self::Metadata(err)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Self {
+        Self::Watch(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Toolchain(msg) | Self::Config(msg) => write!(f, "{msg}"),
+            Self::Metadata(err) => write!(f, "could not get cargo metadata: {err}"),
+            Self::ManifestParse {
+                path,
+                table,
+                source,
+            } => write!(f, "invalid [{table}] in {}: {source}", path.display()),
+            Self::ConfigParse { path, source } => {
+                write!(f, "invalid config file {}: {source}", path.display())
+            }
+            Self::ToolNotFound { tool, source } => write!(f, "could not run `{tool}`: {source}"),
+            Self::ToolFailed { tool, message } => write!(f, "`{tool}` failed: {message}"),
+            Self::Exited { tool, status } => write!(f, "`{tool}` exited with {status}"),
+            Self::NoExecutable => write!(f, "no executable found from build command output"),
+            Self::Io {
+                operation,
+                path,
+                source,
+            } => write!(f, "could not {operation} {}: {source}", path.display()),
+            Self::Icon { path, source } => {
+                write!(f, "could not load icon {}: {source}", path.display())
+            }
+            Self::ThreeDsx { path, source } => {
+                write!(f, "could not convert {}: {source}", path.display())
+            }
+            Self::Ncch { path, source } => {
+                write!(f, "could not build NCCH from {}: {source}", path.display())
+            }
+            Self::Symbolize { path, source } => {
+                write!(
+                    f,
+                    "could not read debug info of {}: {source}",
+                    path.display()
+                )
+            }
+            Self::InvalidFile { path, expected } => {
+                write!(f, "{} is not a valid {expected} file", path.display())
+            }
+            Self::CrashDump { path, source } => {
+                write!(f, "could not read crash dump {}: {source}", path.display())
+            }
+            Self::Link { context, source } => write!(f, "{context}: {source}"),
+            Self::TestsFailed => write!(f, "test failed"),
+            Self::Watch(err) => write!(f, "could not watch for changes: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Metadata(err) => Some(err),
+            Self::ManifestParse { source, .. } => Some(source),
+            Self::ConfigParse { source, .. } => Some(source),
+            Self::ToolNotFound { source, .. }
+            | Self::Io { source, .. }
+            | Self::Link { source, .. } => Some(source),
+            Self::Icon { source, .. } => Some(source),
+            Self::ThreeDsx { source, .. } => Some(source),
+            Self::Ncch { source, .. } => Some(source),
+            Self::Symbolize { source, .. } => Some(source),
+            Self::CrashDump { source, .. } => Some(source),
+            Self::Watch(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let err = Error::io(
+            "read",
+            Path::new("target/foo.elf"),
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(err.to_string(), "could not read target/foo.elf: not found");
+        assert!(std::error::Error::source(&err).is_some());
+
+        let source = serde_json::from_str::<u32>("\"x\"").unwrap_err();
+        let err = Error::ManifestParse {
+            path: PathBuf::from("Cargo.toml"),
+            table: "package.metadata.cargo-3ds",
+            source,
+        };
+        assert!(err
+            .to_string()
+            .starts_with("invalid [package.metadata.cargo-3ds] in Cargo.toml: "));
+    }
+}
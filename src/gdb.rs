@@ -0,0 +1,96 @@
+//! Debugging executables with devkitARM's GDB, connected to a remote stub such
+//! as the one of Luma3DS or of an emulator.
+
+use std::fmt::Write;
+use std::path::Path;
+
+/// The GDB of devkitARM, used when no other is configured.
+pub const DEFAULT_GDB: &str = "arm-none-eabi-gdb";
+
+/// Settings of the GDB session.
+pub struct Session<'a> {
+    /// The ELF executable being debugged.
+    pub elf: &'a Path,
+    /// The `host:port` address of the GDB stub.
+    pub address: &'a str,
+    /// The sysroot of the Rust toolchain, holding the standard library sources
+    /// when `rust-src` is installed.
+    pub rust_sysroot: Option<&'a Path>,
+    /// The commit hash of rustc, which pre-built standard libraries refer to
+    /// their sources by.
+    pub rustc_commit_hash: Option<&'a str>,
+    /// The devkitARM directory, holding the libraries linked with the executable.
+    pub devkitarm: Option<&'a Path>,
+}
+
+impl Session<'_> {
+    /// The script run by GDB when it starts, loading the executable and
+    /// connecting to the stub.
+    pub fn init_script(&self) -> String {
+        let mut script = String::from("# Generated by cargo-3ds\n");
+
+        // Paths are quoted, as they may contain spaces.
+        writeln!(script, "file \"{}\"", self.elf.display()).unwrap();
+
+        if let Some(devkitarm) = self.devkitarm {
+            writeln!(
+                script,
+                "set sysroot \"{}\"",
+                devkitarm.join("arm-none-eabi").display()
+            )
+            .unwrap();
+        }
+
+        // Pre-built standard libraries refer to their sources in `/rustc/<commit hash>`,
+        // while those built with build-std already use the paths of rust-src.
+        if let (Some(sysroot), Some(hash)) = (self.rust_sysroot, self.rustc_commit_hash) {
+            writeln!(
+                script,
+                "set substitute-path /rustc/{hash} \"{}\"",
+                sysroot.join("lib/rustlib/src/rust").display()
+            )
+            .unwrap();
+        }
+
+        writeln!(script, "target remote {}", self.address).unwrap();
+        script
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_script() {
+        let session = Session {
+            elf: Path::new("/app/target/armv6k-nintendo-3ds/debug/app.elf"),
+            address: "192.168.0.2:4003",
+            rust_sysroot: Some(Path::new("/toolchain")),
+            rustc_commit_hash: Some("abc123"),
+            devkitarm: Some(Path::new("/opt/devkitpro/devkitARM")),
+        };
+
+        assert_eq!(
+            session.init_script(),
+            "# Generated by cargo-3ds\n\
+            file \"/app/target/armv6k-nintendo-3ds/debug/app.elf\"\n\
+            set sysroot \"/opt/devkitpro/devkitARM/arm-none-eabi\"\n\
+            set substitute-path /rustc/abc123 \"/toolchain/lib/rustlib/src/rust\"\n\
+            target remote 192.168.0.2:4003\n"
+        );
+
+        let session = Session {
+            rust_sysroot: None,
+            devkitarm: None,
+            ..session
+        };
+        assert_eq!(
+            session.init_script().lines().skip(1).collect::<Vec<_>>(),
+            [
+                "file \"/app/target/armv6k-nintendo-3ds/debug/app.elf\"",
+                "target remote 192.168.0.2:4003"
+            ]
+        );
+    }
+}
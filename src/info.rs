@@ -0,0 +1,478 @@
+//! Inspection of built 3DSX, SMDH and CIA files, for `cargo 3ds info`.
+
+use crate::cia::Cia;
+use crate::ncch;
+use crate::smdh::{Icon, Language, Smdh, Title, FLAG_NAMES, RATING_BOARDS, REGIONS, REGION_FREE};
+use crate::threedsx;
+
+use core::fmt;
+use serde::Serialize;
+
+/// The width of the labels of the text output.
+const LABEL_WIDTH: usize = 14;
+
+/// What a file contains, printed as text or serialized as JSON.
+#[derive(Serialize)]
+#[serde(tag = "format")]
+pub enum FileInfo {
+    #[serde(rename = "3dsx")]
+    ThreeDsx(ThreeDsxInfo),
+    #[serde(rename = "smdh")]
+    Smdh(SmdhInfo),
+    #[serde(rename = "cia")]
+    Cia(CiaInfo),
+}
+
+#[derive(Serialize)]
+pub struct ThreeDsxInfo {
+    version: u32,
+    flags: u32,
+    header_size: u16,
+    segments: Vec<SegmentInfo>,
+    bss_size: u32,
+    smdh: Option<SmdhInfo>,
+    romfs: Option<RomFsInfo>,
+}
+
+#[derive(Serialize)]
+struct SegmentInfo {
+    name: &'static str,
+    size: u32,
+    absolute_relocations: u32,
+    relative_relocations: u32,
+}
+
+#[derive(Serialize)]
+struct RomFsInfo {
+    offset: u64,
+    size: u64,
+}
+
+#[derive(Serialize)]
+pub struct SmdhInfo {
+    titles: Vec<LocalizedTitle>,
+    flags: u32,
+    flag_names: Vec<&'static str>,
+    region_lockout: u32,
+    regions: Vec<&'static str>,
+    ratings: Vec<Rating>,
+    #[serde(skip)]
+    smdh: Smdh,
+}
+
+#[derive(Serialize)]
+struct LocalizedTitle {
+    language: Language,
+    #[serde(flatten)]
+    title: Title,
+}
+
+#[derive(Serialize)]
+struct Rating {
+    board: &'static str,
+    /// The minimum age, or `None` without age restriction.
+    age: Option<u8>,
+    pending: bool,
+}
+
+#[derive(Serialize)]
+pub struct CiaInfo {
+    /// In hexadecimal, since JSON numbers can't hold every title ID.
+    title_id: String,
+    title_version: u16,
+    contents: Vec<ContentInfo>,
+    /// The header of the first content, if it is a partition.
+    partition: Option<PartitionInfo>,
+    smdh: Option<SmdhInfo>,
+}
+
+#[derive(Serialize)]
+struct ContentInfo {
+    id: u32,
+    index: u16,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct PartitionInfo {
+    product_code: String,
+    process_name: String,
+    exefs_size: u64,
+    /// Zero if there is no `RomFS`.
+    romfs_size: u64,
+}
+
+impl FileInfo {
+    /// Read a 3DSX, SMDH or CIA file, recognized by its contents, or `None` if
+    /// it is not a valid one.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        match data.get(..4)? {
+            b"3DSX" => ThreeDsxInfo::read(data).map(Self::ThreeDsx),
+            b"SMDH" => SmdhInfo::read(data).map(Self::Smdh),
+            _ => CiaInfo::read(data).map(Self::Cia),
+        }
+    }
+
+    /// The embedded SMDH, or the file itself if it is one.
+    pub fn smdh(&self) -> Option<&Smdh> {
+        match self {
+            Self::ThreeDsx(info) => info.smdh.as_ref(),
+            Self::Smdh(info) => Some(info),
+            Self::Cia(info) => info.smdh.as_ref(),
+        }
+        .map(|info| &info.smdh)
+    }
+}
+
+impl ThreeDsxInfo {
+    fn read(data: &[u8]) -> Option<Self> {
+        let header = threedsx::Header::parse(data)?;
+
+        let segments = ["code", "rodata", "data"]
+            .into_iter()
+            .zip(header.segment_sizes)
+            .zip(header.relocations)
+            .map(|((name, size), [absolute, relative])| SegmentInfo {
+                name,
+                size,
+                absolute_relocations: absolute,
+                relative_relocations: relative,
+            })
+            .collect();
+
+        let smdh = match header.smdh {
+            Some((offset, size)) => {
+                let start = offset as usize;
+                Some(SmdhInfo::read(data.get(start..start + size as usize)?)?)
+            }
+            None => None,
+        };
+        let romfs = match header.romfs_offset {
+            Some(offset) => Some(RomFsInfo {
+                offset: offset.into(),
+                size: (data.len() as u64).checked_sub(offset.into())?,
+            }),
+            None => None,
+        };
+
+        Some(Self {
+            version: header.version,
+            flags: header.flags,
+            header_size: header.header_size,
+            segments,
+            bss_size: header.bss_size,
+            smdh,
+            romfs,
+        })
+    }
+}
+
+impl SmdhInfo {
+    fn read(data: &[u8]) -> Option<Self> {
+        let smdh = Smdh::from_bytes(data)?;
+
+        let titles = Language::ALL
+            .into_iter()
+            .map(|language| LocalizedTitle {
+                language,
+                title: smdh.titles[language as usize].clone(),
+            })
+            .collect();
+        let flag_names = FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|&(bit, name)| smdh.flags & 1 << bit != 0 && !name.is_empty())
+            .map(|(_, name)| *name)
+            .collect();
+        let regions = REGIONS
+            .iter()
+            .enumerate()
+            .filter(|&(bit, _)| smdh.region_lockout & 1 << bit != 0)
+            .map(|(_, region)| *region)
+            .collect();
+        let ratings = RATING_BOARDS
+            .iter()
+            .zip(smdh.age_ratings)
+            .filter_map(|(board, rating)| {
+                // Only set ratings are shown, in slots used by a board.
+                let board = board.filter(|_| rating & 0x80 != 0)?;
+                Some(Rating {
+                    board,
+                    age: (rating & 0x20 == 0).then_some(rating & 0x1F),
+                    pending: rating & 0x40 != 0,
+                })
+            })
+            .collect();
+
+        Some(Self {
+            titles,
+            flags: smdh.flags,
+            flag_names,
+            region_lockout: smdh.region_lockout,
+            regions,
+            ratings,
+            smdh,
+        })
+    }
+}
+
+impl CiaInfo {
+    fn read(data: &[u8]) -> Option<Self> {
+        let cia = Cia::parse(data)?;
+
+        let contents = cia
+            .contents
+            .iter()
+            .map(|content| ContentInfo {
+                id: content.id,
+                index: content.index,
+                size: content.data.len() as u64,
+            })
+            .collect();
+        let partition = cia
+            .contents
+            .first()
+            .and_then(|content| ncch::Header::parse(content.data))
+            .map(|header| PartitionInfo {
+                product_code: header.product_code,
+                process_name: header.name,
+                exefs_size: header.exefs_size,
+                romfs_size: header.romfs_size,
+            });
+        let smdh = match cia.smdh {
+            Some(smdh) => Some(SmdhInfo::read(smdh)?),
+            None => None,
+        };
+
+        Some(Self {
+            title_id: format!("{:016x}", cia.title_id),
+            title_version: cia.title_version,
+            contents,
+            partition,
+            smdh,
+        })
+    }
+}
+
+/// Write a label and its value, aligned with the others.
+fn field(f: &mut fmt::Formatter, label: &str, value: impl fmt::Display) -> fmt::Result {
+    writeln!(f, "{:<LABEL_WIDTH$}{value}", format!("{label}:"))
+}
+
+fn list<T: fmt::Display>(items: &[T], empty: &str) -> String {
+    if items.is_empty() {
+        return String::from(empty);
+    }
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ThreeDsx(info) => info.fmt(f),
+            Self::Smdh(info) => {
+                field(f, "Format", "SMDH")?;
+                info.fmt(f)
+            }
+            Self::Cia(info) => info.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for ThreeDsxInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        field(
+            f,
+            "Format",
+            format_args!(
+                "3DSX (version {}, flags {:#x}, header of {:#x} bytes)",
+                self.version, self.flags, self.header_size
+            ),
+        )?;
+        writeln!(f, "Segments:")?;
+        for segment in &self.segments {
+            write!(f, "  {:<10}{:#010x} bytes", segment.name, segment.size)?;
+            if segment.name == "data" {
+                write!(f, " + {:#x} bytes of BSS", self.bss_size)?;
+            }
+            writeln!(
+                f,
+                ", {} absolute and {} relative relocations",
+                segment.absolute_relocations, segment.relative_relocations
+            )?;
+        }
+        match &self.romfs {
+            Some(romfs) => field(
+                f,
+                "RomFS",
+                format_args!("{:#x} bytes at {:#x}", romfs.size, romfs.offset),
+            )?,
+            None => field(f, "RomFS", "none")?,
+        }
+        match &self.smdh {
+            Some(smdh) => smdh.fmt(f),
+            None => field(f, "SMDH", "none"),
+        }
+    }
+}
+
+impl fmt::Display for SmdhInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Titles:")?;
+        for LocalizedTitle { language, title } in &self.titles {
+            writeln!(f, "  {:<6}{}", language.code(), title.short_description)?;
+            writeln!(f, "  {:<6}{}", "", title.long_description)?;
+            writeln!(f, "  {:<6}{}", "", title.publisher)?;
+        }
+        field(
+            f,
+            "Flags",
+            format_args!("{} ({:#06x})", list(&self.flag_names, "none"), self.flags),
+        )?;
+        if self.region_lockout == REGION_FREE {
+            field(f, "Regions", "region free")?;
+        } else {
+            field(f, "Regions", list(&self.regions, "none"))?;
+        }
+        field(f, "Ratings", list(&self.ratings, "none"))
+    }
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.age {
+            _ if self.pending => write!(f, "{} pending", self.board),
+            Some(age) => write!(f, "{} {age}+", self.board),
+            None => write!(f, "{} all ages", self.board),
+        }
+    }
+}
+
+impl fmt::Display for CiaInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        field(f, "Format", "CIA")?;
+        field(
+            f,
+            "Title ID",
+            format_args!("{} (version {})", self.title_id, self.title_version),
+        )?;
+        writeln!(f, "Contents:")?;
+        for content in &self.contents {
+            writeln!(
+                f,
+                "  {:<10}ID {:08x}, {:#x} bytes",
+                content.index, content.id, content.size
+            )?;
+        }
+        if let Some(partition) = &self.partition {
+            field(f, "Product code", &partition.product_code)?;
+            field(f, "Process name", &partition.process_name)?;
+            field(
+                f,
+                "ExeFS",
+                format_args!("{:#x} bytes", partition.exefs_size),
+            )?;
+            match partition.romfs_size {
+                0 => field(f, "RomFS", "none")?,
+                size => field(f, "RomFS", format_args!("{size:#x} bytes"))?,
+            }
+        }
+        match &self.smdh {
+            Some(smdh) => smdh.fmt(f),
+            None => field(f, "SMDH", "none"),
+        }
+    }
+}
+
+/// The file names of the exported icons, e.g. `app.small.png`.
+pub fn icon_files<'a>(smdh: &'a Smdh, stem: &str) -> [(String, &'a Icon); 2] {
+    [
+        (format!("{stem}.small.png"), &smdh.small_icon),
+        (format!("{stem}.large.png"), &smdh.large_icon),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::smdh::LARGE_ICON_SIZE;
+    use crate::threedsx::tests::build_elf;
+    use crate::threedsx::ThreeDsx;
+
+    fn test_smdh() -> Smdh {
+        let mut smdh = Smdh::new(
+            Title {
+                short_description: String::from("Hello"),
+                long_description: String::from("A test app"),
+                publisher: String::from("Ferris"),
+            },
+            Icon::from_rgba(LARGE_ICON_SIZE, &[[0, 0, 0, 255]; 48 * 48]),
+        );
+        smdh.age_ratings[1] = 0x80 | 10;
+        smdh.age_ratings[4] = 0x80 | 0x40;
+        smdh
+    }
+
+    #[test]
+    fn threedsx_info() {
+        let elf = build_elf(&[0; 8], b"rodata", b"data", &[]);
+        let mut data = Vec::new();
+        ThreeDsx::from_elf(&elf)
+            .unwrap()
+            .write_to(&mut data, Some(&test_smdh().to_bytes()), None)
+            .unwrap();
+
+        let info = FileInfo::read(&data).unwrap();
+        let text = info.to_string();
+        assert!(
+            text.starts_with("Format:       3DSX (version 0, flags 0x0, header of 0x2c bytes)\n")
+        );
+        assert!(text.contains("  data      0x00000004 bytes + 0x10 bytes of BSS, 0 absolute"));
+        assert!(text.contains("RomFS:        none\n"));
+        assert!(text.contains("  fr    Hello\n        A test app\n        Ferris\n"));
+        assert!(text.contains("Flags:        visible, allow 3D, record usage (0x0105)\n"));
+        assert!(text.contains("Regions:      region free\n"));
+        assert!(text.ends_with("Ratings:      ESRB 10+, PEGI pending\n"));
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["format"], "3dsx");
+        assert_eq!(json["segments"][1]["size"], 0x1000);
+        assert_eq!(json["smdh"]["titles"][1]["language"], "en");
+        assert_eq!(json["smdh"]["titles"][1]["publisher"], "Ferris");
+        assert_eq!(json["smdh"]["ratings"][0]["age"], 10);
+        assert_eq!(json["romfs"], serde_json::Value::Null);
+
+        assert!(info.smdh().is_some());
+        assert!(FileInfo::read(&data[..0x40]).is_none());
+    }
+
+    #[test]
+    fn cia_info() {
+        let elf = build_elf(&[0; 8], &[], &[], &[]);
+        let smdh = test_smdh().to_bytes();
+        let partition = ncch::Ncch {
+            title_id: 0x0004_0000_0FF3_FF00,
+            product_code: "CTR-P-TEST",
+            name: "test",
+            elf: &elf,
+            smdh: &smdh,
+            banner: None,
+            romfs: None,
+        }
+        .build()
+        .unwrap();
+        let mut data = Vec::new();
+        crate::cia::write_cia(&mut data, 0x0004_0000_0FF3_FF00, &partition, &smdh).unwrap();
+
+        let text = FileInfo::read(&data).unwrap().to_string();
+        assert!(text.contains("Title ID:     000400000ff3ff00 (version 0)\n"));
+        assert!(text.contains("Product code: CTR-P-TEST\nProcess name: test\n"));
+        assert!(text.contains("RomFS:        none\n"));
+        assert!(text.contains("  ja    Hello\n"));
+    }
+}
@@ -1,28 +1,71 @@
+pub mod cia;
 pub mod command;
+pub mod config;
+pub mod crash;
+pub mod doctor;
+pub mod elf;
+pub mod emulator;
+pub mod error;
+pub mod gdb;
+pub mod info;
+pub mod metadata;
+pub mod ncch;
+pub mod ncsd;
+pub mod romfs;
+pub mod scaffold;
+pub mod smdh;
+pub mod symbolize;
+pub mod test_results;
+pub mod threedslink;
+pub mod threedsx;
+pub mod watch;
 
-use crate::command::CargoCmd;
+use crate::command::{CargoCmd, Info, Init, New, NewOptions, Romfs, RomfsAction, Run, Test};
+use crate::config::UserConfig;
+use crate::crash::Dump;
+use crate::emulator::EmulatorCommand;
+pub use crate::error::Error;
+use crate::info::FileInfo;
+use crate::metadata::Metadata;
+use crate::ncch::Ncch;
+use crate::romfs::RomFs;
+use crate::smdh::{Icon, Smdh, Title, LARGE_ICON_SIZE, SMALL_ICON_SIZE};
+use crate::symbolize::Symbolizer;
+use crate::test_results::TestResult;
+use crate::threedslink::Server;
+use crate::threedsx::ThreeDsx;
 
-use cargo_metadata::{Message, MetadataCommand};
+use cargo_metadata::{Artifact, Message, MetadataCommand, Package};
 use rustc_version::Channel;
 use semver::Version;
 use serde::Deserialize;
 use tee::TeeReader;
 
 use core::fmt;
-use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::Ipv4Addr;
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus, Stdio};
-use std::{env, io, process};
+use std::time::Duration;
+use std::{env, io, thread};
 
 /// Build a command using [`make_cargo_build_command`] and execute it,
 /// parsing and returning the messages from the spawned process.
 ///
 /// For commands that produce an executable output, this function will build the
 /// `.elf` binary that can be used to create other 3ds files.
-pub fn run_cargo(cmd: &CargoCmd, message_format: Option<String>) -> (ExitStatus, Vec<Message>) {
-    let mut command = make_cargo_build_command(cmd, &message_format);
-    let mut process = command.spawn().unwrap();
-    let command_stdout = process.stdout.take().unwrap();
+pub fn run_cargo(
+    cmd: &CargoCmd,
+    message_format: Option<String>,
+) -> Result<(ExitStatus, Vec<Message>), Error> {
+    let mut command = make_cargo_build_command(cmd, &message_format)?;
+    let tool = command.get_program().to_string_lossy().into_owned();
+    let mut process = command.spawn().map_err(|source| Error::ToolNotFound {
+        tool: tool.clone(),
+        source,
+    })?;
+    let command_stdout = process.stdout.take().expect("stdout is piped");
 
     let mut tee_reader;
     let mut stdout_reader;
@@ -37,31 +80,52 @@ pub fn run_cargo(cmd: &CargoCmd, message_format: Option<String>) -> (ExitStatus,
         &mut tee_reader
     };
 
+    let output_error = |e: io::Error| Error::ToolFailed {
+        tool: tool.clone(),
+        message: format!("could not read its output: {e}"),
+    };
     let messages = Message::parse_stream(buf_reader)
         .collect::<io::Result<_>>()
-        .unwrap();
+        .map_err(output_error)?;
+    let status = process.wait().map_err(output_error)?;
 
-    (process.wait().unwrap(), messages)
+    Ok((status, messages))
 }
 
 /// Create the cargo build command, but don't execute it.
 /// If there is no pre-built std detected in the sysroot, `build-std` is used.
-pub fn make_cargo_build_command(cmd: &CargoCmd, message_format: &Option<String>) -> Command {
+pub fn make_cargo_build_command(
+    cmd: &CargoCmd,
+    message_format: &Option<String>,
+) -> Result<Command, Error> {
+    let (cmd_str, cargo_args) = match cmd {
+        CargoCmd::Build(build) => ("build", build.cargo_args.cargo_args()),
+        CargoCmd::Run(run) => ("build", run.cargo_args.cargo_args()),
+        CargoCmd::Debug(debug) => ("build", debug.cargo_args.cargo_args()),
+        CargoCmd::Addr2line(addr2line) => ("build", &addr2line.cargo_args[..]),
+        CargoCmd::Crash(crash) => ("build", &crash.cargo_args[..]),
+        CargoCmd::Test(test) => ("test", test.run_args.cargo_args.cargo_args()),
+        CargoCmd::Passthrough(other) => (other[0].as_str(), &other[1..]),
+        CargoCmd::New(_)
+        | CargoCmd::Init(_)
+        | CargoCmd::Doctor
+        | CargoCmd::Info(_)
+        | CargoCmd::Romfs(_) => {
+            return Err(Error::Config(String::from(
+                "this command does not run cargo",
+            )))
+        }
+    };
+
     let rust_flags = env::var("RUSTFLAGS").unwrap_or_default()
         + &format!(
-            " -L{}/libctru/lib -lctru",
-            env::var("DEVKITPRO").expect("DEVKITPRO is not defined as an environment variable")
+            " -L{} -lctru",
+            libctru_lib_dir(Path::new(&devkitpro()?)).display()
         );
     let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
-    let sysroot = find_sysroot();
+    let sysroot = find_sysroot()?;
     let mut command = Command::new(cargo);
 
-    let cmd_str = match cmd {
-        CargoCmd::Build(_) | CargoCmd::Run(_) => "build",
-        CargoCmd::Test(_) => "test",
-        CargoCmd::Passthrough(cmd) => &cmd[0],
-    };
-
     command
         .env("RUSTFLAGS", rust_flags)
         .arg(cmd_str)
@@ -74,22 +138,16 @@ pub fn make_cargo_build_command(cmd: &CargoCmd, message_format: &Option<String>)
                 .unwrap_or(CargoCmd::DEFAULT_MESSAGE_FORMAT),
         );
 
-    if !sysroot.join("lib/rustlib/armv6k-nintendo-3ds").exists() {
+    if !prebuilt_std_dir(&sysroot).exists() {
         eprintln!("No pre-build std found, using build-std");
         command.arg("-Zbuild-std=panic_abort,std");
     }
 
-    let cargo_args = match cmd {
-        CargoCmd::Build(cargo_args) => cargo_args.cargo_args(),
-        CargoCmd::Run(run) => run.cargo_args.cargo_args(),
-        CargoCmd::Test(test) => {
-            // We can't run 3DS executables on the host, so pass --no-run here and
-            // send the executable with 3dslink later, if the user wants
-            command.arg("--no-run");
-            test.run_args.cargo_args.cargo_args()
-        }
-        CargoCmd::Passthrough(other) => &other[1..],
-    };
+    if let CargoCmd::Test(_) = cmd {
+        // We can't run 3DS executables on the host, so pass --no-run here and
+        // send the executable with 3dslink later, if the user wants
+        command.arg("--no-run");
+    }
 
     command
         .args(cargo_args)
@@ -97,37 +155,70 @@ pub fn make_cargo_build_command(cmd: &CargoCmd, message_format: &Option<String>)
         .stdin(Stdio::inherit())
         .stderr(Stdio::inherit());
 
-    command
+    Ok(command)
+}
+
+/// The directory libctru is linked from.
+pub(crate) fn libctru_lib_dir(devkitpro: &Path) -> PathBuf {
+    devkitpro.join("libctru/lib")
+}
+
+/// The directory of the pre-built standard library for the 3DS, if installed.
+pub(crate) fn prebuilt_std_dir(sysroot: &Path) -> PathBuf {
+    sysroot.join("lib/rustlib/armv6k-nintendo-3ds")
+}
+
+/// Get the root of the devkitPro installation from the `DEVKITPRO` environment variable.
+fn devkitpro() -> Result<String, Error> {
+    env::var("DEVKITPRO").map_err(|_| {
+        Error::Toolchain(String::from(
+            "DEVKITPRO is not defined as an environment variable",
+        ))
+    })
 }
 
 /// Finds the sysroot path of the current toolchain
-pub fn find_sysroot() -> PathBuf {
-    let sysroot = env::var("SYSROOT").ok().unwrap_or_else(|| {
-        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+pub fn find_sysroot() -> Result<PathBuf, Error> {
+    if let Ok(sysroot) = env::var("SYSROOT") {
+        return Ok(PathBuf::from(sysroot.trim()));
+    }
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = Command::new(&rustc)
+        .arg("--print")
+        .arg("sysroot")
+        .output()
+        .map_err(|source| Error::ToolNotFound {
+            tool: rustc.clone(),
+            source,
+        })?;
+    if !output.status.success() {
+        return Err(Error::ToolFailed {
+            tool: format!("{rustc} --print sysroot"),
+            message: output.status.to_string(),
+        });
+    }
 
-        let output = Command::new(&rustc)
-            .arg("--print")
-            .arg("sysroot")
-            .output()
-            .unwrap_or_else(|_| panic!("Failed to run `{rustc} -- print sysroot`"));
-        String::from_utf8(output.stdout).expect("Failed to parse sysroot path into a UTF-8 string")
-    });
+    let sysroot = String::from_utf8(output.stdout).map_err(|_| Error::ToolFailed {
+        tool: format!("{rustc} --print sysroot"),
+        message: String::from("the sysroot path is not valid UTF-8"),
+    })?;
 
-    PathBuf::from(sysroot.trim())
+    Ok(PathBuf::from(sysroot.trim()))
 }
 
 /// Checks the current rust version and channel.
-/// Exits if the minimum requirement is not met.
-pub fn check_rust_version() {
-    let rustc_version = rustc_version::version_meta().unwrap();
+/// Returns an error if the minimum requirement is not met.
+pub fn check_rust_version() -> Result<(), Error> {
+    let rustc_version = rustc_version::version_meta()
+        .map_err(|e| Error::Toolchain(format!("could not get the rustc version: {e}")))?;
 
     if rustc_version.channel > Channel::Nightly {
-        eprintln!("cargo-3ds requires a nightly rustc version.");
-        eprintln!(
-            "Please run `rustup override set nightly` to use nightly in the \
-            current directory."
-        );
-        process::exit(1);
+        return Err(Error::Toolchain(String::from(
+            "cargo-3ds requires a nightly rustc version.\n\
+            Please run `rustup override set nightly` to use nightly in the \
+            current directory.",
+        )));
     }
 
     let old_version = MINIMUM_RUSTC_VERSION
@@ -140,204 +231,891 @@ pub fn check_rust_version() {
     let old_commit = match rustc_version.commit_date {
         None => false,
         Some(date) => {
-            MINIMUM_COMMIT_DATE
-                > CommitDate::parse(&date).expect("could not parse `rustc --version` commit date")
+            let date = CommitDate::parse(&date).ok_or_else(|| {
+                Error::Toolchain(format!(
+                    "could not parse `rustc --version` commit date: {date}"
+                ))
+            })?;
+            MINIMUM_COMMIT_DATE > date
         }
     };
 
     if old_version || old_commit {
-        eprintln!(
-            "cargo-3ds requires rustc nightly version >= {}",
-            MINIMUM_COMMIT_DATE,
-        );
-        eprintln!("Please run `rustup update nightly` to upgrade your nightly version");
-
-        process::exit(1);
+        return Err(Error::Toolchain(format!(
+            "cargo-3ds requires rustc nightly version >= {MINIMUM_COMMIT_DATE}\n\
+            Please run `rustup update nightly` to upgrade your nightly version"
+        )));
     }
+
+    Ok(())
 }
 
 /// Parses messages returned by the executed cargo command from [`build_elf`].
-/// The returned [`CTRConfig`] is then used for further building in and execution
-/// in [`build_smdh`], [`build_3dsx`], and [`link`].
-pub fn get_metadata(messages: &[Message]) -> CTRConfig {
-    let metadata = MetadataCommand::new()
-        .exec()
-        .expect("Failed to get cargo metadata");
-
-    let mut package = None;
-    let mut artifact = None;
-
-    // Extract the final built executable. We may want to fail in cases where
-    // multiple executables, or none, were built?
-    for message in messages.iter().rev() {
-        if let Message::CompilerArtifact(art) = message {
-            if art.executable.is_some() {
-                package = Some(metadata[&art.package_id].clone());
-                artifact = Some(art.clone());
-
-                break;
+/// A [`CTRConfig`] is returned for each executable that was built, which is
+/// then used for further building in and execution in [`build_smdh`],
+/// [`build_3dsx`], and [`link`].
+pub fn get_metadata(messages: &[Message]) -> Result<Vec<CTRConfig>, Error> {
+    let metadata = MetadataCommand::new().exec()?;
+
+    // Paths in the workspace settings are relative to the workspace root,
+    // while those of packages are relative to their manifest.
+    let mut workspace_metadata = read_metadata(
+        &metadata.workspace_metadata,
+        metadata.workspace_root.join("Cargo.toml").as_std_path(),
+        "workspace.metadata.cargo-3ds",
+    )?;
+    workspace_metadata.rebase_paths(metadata.workspace_root.as_std_path());
+
+    let mut configs: Vec<CTRConfig> = Vec::new();
+
+    for message in messages {
+        if let Message::CompilerArtifact(artifact) = message {
+            let Some(executable) = &artifact.executable else {
+                continue;
+            };
+            // The same artifact may be reported more than once, e.g. when it
+            // is both a test and a regular binary of a build.
+            if configs
+                .iter()
+                .any(|config| config.target_path == executable.as_std_path())
+            {
+                continue;
             }
+
+            configs.push(get_artifact_config(
+                &metadata[&artifact.package_id],
+                artifact,
+                executable.clone().into(),
+                &workspace_metadata,
+            )?);
         }
     }
-    if package.is_none() || artifact.is_none() {
-        eprintln!("No executable found from build command output!");
-        process::exit(1);
+
+    if configs.is_empty() {
+        return Err(Error::NoExecutable);
     }
 
-    let (package, artifact) = (package.unwrap(), artifact.unwrap());
+    Ok(configs)
+}
 
-    let mut icon = String::from("./icon.png");
+/// Read the `cargo-3ds` settings of a package or workspace, from its `table`
+/// in the manifest at `path`.
+fn read_metadata(
+    metadata: &serde_json::Value,
+    path: &Path,
+    table: &'static str,
+) -> Result<Metadata, Error> {
+    let metadata =
+        Metadata::from_package_metadata(metadata).map_err(|source| Error::ManifestParse {
+            path: path.to_path_buf(),
+            table,
+            source,
+        })?;
+    metadata
+        .check_unique_id()
+        .map_err(|msg| Error::Config(format!("{table} in {}: {msg}", path.display())))?;
 
-    if !Path::new(&icon).exists() {
-        icon = format!(
-            "{}/libctru/default_icon.png",
-            env::var("DEVKITPRO").unwrap()
-        );
+    Ok(metadata)
+}
+
+/// Creates the [`CTRConfig`] of an executable artifact of a package, using the
+/// workspace settings as defaults for those of the package.
+fn get_artifact_config(
+    package: &Package,
+    artifact: &Artifact,
+    target_path: PathBuf,
+    workspace_metadata: &Metadata,
+) -> Result<CTRConfig, Error> {
+    let metadata = read_metadata(
+        &package.metadata,
+        package.manifest_path.as_std_path(),
+        "package.metadata.cargo-3ds",
+    )?;
+
+    // for now assume a single "kind" per executable artifact
+    let kind = artifact.target.kind[0].as_str();
+    let mut metadata = metadata
+        .inherit(workspace_metadata.clone())
+        .for_target(kind, &artifact.target.name);
+    if let Some(profile) = artifact_profile(&target_path) {
+        metadata = metadata.for_profile(&profile);
     }
 
-    // for now assume a single "kind" since we only support one output artifact
-    let name = match artifact.target.kind[0].as_ref() {
+    let manifest_dir = package.manifest_path.parent().unwrap().as_std_path();
+    let icon = match &metadata.icon {
+        Some(icon) => manifest_dir.join(icon),
+        None if manifest_dir.join("icon.png").exists() => manifest_dir.join("icon.png"),
+        None => PathBuf::from(format!("{}/libctru/default_icon.png", devkitpro()?)),
+    };
+
+    let name = match kind {
         "bin" | "lib" | "rlib" | "dylib" if artifact.target.test => {
             format!("{} tests", artifact.target.name)
         }
         "example" => {
             format!("{} - {} example", artifact.target.name, package.name)
         }
-        _ => artifact.target.name,
-    };
-
-    let author = match package.authors.as_slice() {
-        [name, ..] => name.clone(),
-        [] => String::from("Unspecified Author"), // as standard with the devkitPRO toolchain
+        _ => artifact.target.name.clone(),
     };
 
-    CTRConfig {
-        name,
-        author,
-        description: package
+    let title = metadata.default_title(
+        &name,
+        package
             .description
-            .clone()
-            .unwrap_or_else(|| String::from("Homebrew Application")),
+            .as_deref()
+            .unwrap_or("Homebrew Application"),
+        &package.authors,
+    );
+
+    Ok(CTRConfig {
+        name: title.short_description,
+        author: title.publisher,
+        description: title.long_description,
         icon,
-        target_path: artifact.executable.unwrap().into(),
-        cargo_manifest_path: package.manifest_path.into(),
+        target_path,
+        cargo_manifest_path: package.manifest_path.clone().into(),
+        metadata,
+    })
+}
+
+/// Get the name of the profile an executable was built with, from its path in
+/// the target directory (e.g. `target/armv6k-nintendo-3ds/release/foo.elf`).
+/// The `dev` profile is built in the `debug` directory.
+fn artifact_profile(executable: &Path) -> Option<String> {
+    let mut components = executable
+        .components()
+        .skip_while(|component| component.as_os_str() != "armv6k-nintendo-3ds");
+    components.next()?;
+
+    match components.next()?.as_os_str().to_str()? {
+        "debug" => Some(String::from("dev")),
+        profile => Some(profile.to_string()),
     }
 }
 
-/// Builds the smdh using `smdhtool`.
-/// This will fail if `smdhtool` is not within the running directory or in a directory found in $PATH
-pub fn build_smdh(config: &CTRConfig) {
-    let mut process = Command::new("smdhtool")
-        .arg("--create")
-        .arg(&config.name)
-        .arg(&config.description)
-        .arg(&config.author)
-        .arg(&config.icon)
-        .arg(config.path_smdh())
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .expect("smdhtool command failed, most likely due to 'smdhtool' not being in $PATH");
+/// Builds the smdh from the name, description, author and icon in the config,
+/// and the localized titles in the metadata.
+pub fn build_smdh(config: &CTRConfig) -> Result<(), Error> {
+    let image = image::open(&config.icon).map_err(|source| Error::Icon {
+        path: config.icon.clone(),
+        source,
+    })?;
+    if image.width() != image.height() {
+        eprintln!(
+            "warning: icon {} is {}x{}, it will be stretched to a square",
+            config.icon.display(),
+            image.width(),
+            image.height()
+        );
+    }
 
-    let status = process.wait().unwrap();
+    let title = Title {
+        short_description: config.name.clone(),
+        long_description: config.description.clone(),
+        publisher: config.author.clone(),
+    };
 
-    if !status.success() {
-        process::exit(status.code().unwrap_or(1));
+    let mut smdh = Smdh::with_icons(
+        title.clone(),
+        Icon::from_image(&image, SMALL_ICON_SIZE),
+        Icon::from_image(&image, LARGE_ICON_SIZE),
+    );
+    for (language, localized) in config.metadata.localized_titles(&title) {
+        smdh.set_title(language, localized);
     }
+
+    let path = config.path_smdh();
+    std::fs::write(&path, smdh.to_bytes()).map_err(|e| Error::io("write", &path, e))
+}
+
+/// Builds the `RomFS` image from the directory returned by [`get_romfs_path`],
+/// if any, and writes it next to the executable.
+pub fn build_romfs(config: &CTRConfig) -> Result<Option<RomFs>, Error> {
+    let Some(romfs_path) = get_romfs_path(config)? else {
+        return Ok(None);
+    };
+
+    eprintln!("Adding RomFS from {}", romfs_path.display());
+    let romfs =
+        RomFs::from_dir(&romfs_path).map_err(|e| Error::io("read RomFS dir", &romfs_path, e))?;
+
+    let path = config.path_romfs();
+    write_file(&path, |out| romfs.write_to(out)).map_err(|e| Error::io("write", &path, e))?;
+
+    Ok(Some(romfs))
 }
 
-/// Builds the 3dsx using `3dsxtool`.
-/// This will fail if `3dsxtool` is not within the running directory or in a directory found in $PATH
-pub fn build_3dsx(config: &CTRConfig) {
-    let mut command = Command::new("3dsxtool");
-    let mut process = command
-        .arg(&config.target_path)
-        .arg(config.path_3dsx())
-        .arg(format!("--smdh={}", config.path_smdh().to_string_lossy()));
+/// Builds the 3dsx from the ELF executable, the smdh built by [`build_smdh`],
+/// and the `RomFS` image built by [`build_romfs`], if any.
+pub fn build_3dsx(config: &CTRConfig, romfs: Option<&RomFs>) -> Result<(), Error> {
+    let elf = read_file(&config.target_path)?;
+    let smdh = read_file(&config.path_smdh())?;
+
+    let threedsx = ThreeDsx::from_elf(&elf).map_err(|source| Error::ThreeDsx {
+        path: config.target_path.clone(),
+        source,
+    })?;
+
+    let path = config.path_3dsx();
+    write_file(&path, |out| threedsx.write_to(out, Some(&smdh), romfs))
+        .map_err(|e| Error::io("write", &path, e))
+}
+
+/// Builds the NCCH partition of installable titles and cartridge images from
+/// the ELF executable, the smdh built by [`build_smdh`], the banner set in the
+/// metadata and the `RomFS` image built by [`build_romfs`], if any.
+pub fn build_ncch(config: &CTRConfig, romfs: Option<&RomFs>) -> Result<Vec<u8>, Error> {
+    let elf = read_file(&config.target_path)?;
+    let smdh = read_file(&config.path_smdh())?;
+    let banner = match &config.metadata.banner {
+        Some(banner) => {
+            let path = config.manifest_dir().join(banner);
+            Some(std::fs::read(&path).map_err(|e| Error::io("read banner", &path, e))?)
+        }
+        None => None,
+    };
+
+    Ncch {
+        title_id: config.title_id()?,
+        product_code: config.metadata.product_code(),
+        name: &config.name,
+        elf: &elf,
+        smdh: &smdh,
+        banner: banner.as_deref(),
+        romfs,
+    }
+    .build()
+    .map_err(|source| Error::Ncch {
+        path: config.target_path.clone(),
+        source,
+    })
+}
+
+/// Builds an installable CIA from the partition built by [`build_ncch`].
+pub fn build_cia(config: &CTRConfig, partition: &[u8]) -> Result<(), Error> {
+    let smdh = read_file(&config.path_smdh())?;
+    let title_id = config.title_id()?;
+
+    let path = config.path_cia();
+    write_file(&path, |out| cia::write_cia(out, title_id, partition, &smdh))
+        .map_err(|e| Error::io("write", &path, e))
+}
+
+/// Builds a cartridge image from the partition built by [`build_ncch`].
+pub fn build_cci(config: &CTRConfig, partition: &[u8]) -> Result<(), Error> {
+    let path = config.path_cci();
+    write_file(&path, |out| {
+        ncsd::write_cci(
+            out,
+            partition,
+            config.metadata.media_size,
+            config.metadata.card_type.unwrap_or_default(),
+        )
+    })
+    .map_err(|e| {
+        if e.kind() == io::ErrorKind::InvalidInput {
+            Error::Config(format!("could not build {}: {e}", path.display()))
+        } else {
+            Error::io("write", &path, e)
+        }
+    })
+}
+
+/// Read a whole file, e.g. an executable built by cargo.
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| Error::io("read", path, e))
+}
+
+/// Create a file and write to it through a buffer.
+fn write_file(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out)?;
+    out.flush()
+}
+
+/// Link the generated 3dsx to a 3ds to execute and test using the 3dslink protocol.
+/// If no address is given, the 3ds is found by broadcasting on the local network.
+pub fn link(config: &CTRConfig, run_args: &Run) -> Result<(), Error> {
+    let address = find_device(run_args)?;
+
+    // Listen before sending, so we're ready when the executable connects back.
+    let server = run_args.server.then(|| bind_server(address)).transpose()?;
+
+    send_3dsx(config, run_args, address)?;
+
+    if let Some(server) = server {
+        server.serve(io::stdout()).map_err(|source| Error::Link {
+            context: String::from("3dslink server failed"),
+            source,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Sends executables to the same 3ds each time they are rebuilt, e.g. in watch
+/// mode. If requested, the 3dslink server keeps running in the background
+/// between sends, so that the output of each run is printed.
+pub struct Linker {
+    address: Ipv4Addr,
+}
+
+impl Linker {
+    /// Find the 3ds and start the 3dslink server, if requested.
+    pub fn start(run_args: &Run) -> Result<Self, Error> {
+        let address = find_device(run_args)?;
+        if run_args.server {
+            let server = bind_server(address)?;
+            thread::spawn(move || {
+                if let Err(e) = server.serve(io::stdout()) {
+                    eprintln!("error: 3dslink server failed: {e}");
+                }
+            });
+        }
+
+        Ok(Self { address })
+    }
+
+    /// Send the 3dsx to the 3ds.
+    pub fn send(&self, config: &CTRConfig, run_args: &Run) -> Result<(), Error> {
+        send_3dsx(config, run_args, self.address)
+    }
+}
+
+/// How long a test executable has to connect back after being sent.
+const TEST_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Send each generated test 3dsx to a 3ds in turn, printing its output as it
+/// is received through the 3dslink server, then print a summary of the results
+/// of all of them. Returns [`Error::TestsFailed`] if any test failed.
+pub fn run_tests(configs: &[CTRConfig], test: &Test) -> Result<(), Error> {
+    let run_args = &test.run_args;
+
+    let address = find_device(run_args)?;
+    let server = bind_server(address)?;
+
+    let mut results = Vec::new();
+    for config in configs {
+        eprintln!("Running {}", config.path_3dsx().display());
+        send_3dsx(config, run_args, address)?;
+
+        // A test executable that crashes, or never redirects its stdio, must
+        // not keep the next ones from running.
+        let session = server
+            .accept_timeout(TEST_CONNECT_TIMEOUT)
+            .and_then(|session| {
+                let timeout =
+                    (test.output_timeout > 0).then(|| Duration::from_secs(test.output_timeout));
+                session.set_read_timeout(timeout)?;
+                Ok(session)
+            });
+        let result = match session {
+            Ok(session) => {
+                let (result, copied) = test_results::capture(BufReader::new(session), io::stdout());
+                if let Err(e) = copied {
+                    eprintln!("Lost connection to {address}: {e}");
+                }
+                result
+            }
+            Err(e) => {
+                eprintln!("No connection from {address}: {e}");
+                None
+            }
+        };
+        results.push((config.path_3dsx(), result));
+    }
+
+    let status = |ok| if ok { "ok" } else { "FAILED" };
+    let mut total = TestResult::default();
+    let mut all_reported = true;
+
+    eprintln!();
+    eprintln!("Test summary:");
+    for (path, result) in &results {
+        match result {
+            Some(result) => {
+                eprintln!("  {}: {}. {result}", path.display(), status(result.is_ok()));
+                total += *result;
+            }
+            None => {
+                eprintln!("  {}: FAILED. no test result reported", path.display());
+                all_reported = false;
+            }
+        }
+    }
+
+    let ok = all_reported && total.is_ok();
+    eprintln!("test result: {}. {total}", status(ok));
+
+    if ok {
+        Ok(())
+    } else {
+        Err(Error::TestsFailed)
+    }
+}
+
+/// Run each generated 3dsx in an emulator in turn, stopping at the first one
+/// that fails.
+pub fn run_in_emulator(configs: &[CTRConfig], run_args: &Run) -> Result<(), Error> {
+    let user_config = UserConfig::load()?;
+    for config in configs {
+        let emulator = find_emulator(config, run_args, &user_config)?;
 
-    // If romfs directory exists, automatically include it
-    let (romfs_path, is_default_romfs) = get_romfs_path(config);
-    if romfs_path.is_dir() {
-        eprintln!("Adding RomFS from {}", romfs_path.display());
-        process = process.arg(format!("--romfs={}", romfs_path.to_string_lossy()));
-    } else if !is_default_romfs {
         eprintln!(
-            "Could not find configured RomFS dir: {}",
-            romfs_path.display()
+            "Running {} in {}",
+            config.path_3dsx().display(),
+            emulator.program.display()
         );
-        process::exit(1);
+        let status = emulator
+            .command(&config.path_3dsx())
+            .status()
+            .map_err(|source| Error::ToolNotFound {
+                tool: emulator.program.display().to_string(),
+                source,
+            })?;
+        if !status.success() {
+            return Err(Error::Exited {
+                tool: emulator.program.display().to_string(),
+                status,
+            });
+        }
     }
 
-    let mut process = process
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .expect("3dsxtool command failed, most likely due to '3dsxtool' not being in $PATH");
+    Ok(())
+}
 
-    let status = process.wait().unwrap();
+/// Get the emulator from the arguments, the package metadata, or the user
+/// config file, in that order.
+fn find_emulator(
+    config: &CTRConfig,
+    run_args: &Run,
+    user_config: &UserConfig,
+) -> Result<EmulatorCommand, Error> {
+    if let Some(Some(path)) = &run_args.emulator {
+        return Ok(EmulatorCommand::new(path.clone()));
+    }
 
-    if !status.success() {
-        process::exit(status.code().unwrap_or(1));
+    if let Some(emulator) = &config.metadata.emulator {
+        let mut emulator = emulator.clone();
+        emulator.rebase(config.manifest_dir());
+        return Ok(emulator);
     }
+
+    user_config.emulator.clone().ok_or_else(|| {
+        Error::Config(String::from(
+            "no emulator configured: pass its path with `--emulator=PATH`, or set \
+            `emulator` in [package.metadata.cargo-3ds] or in the user config file \
+            (~/.config/cargo-3ds/config.toml)",
+        ))
+    })
 }
 
-/// Link the generated 3dsx to a 3ds to execute and test using `3dslink`.
-/// This will fail if `3dslink` is not within the running directory or in a directory found in $PATH
-pub fn link(config: &CTRConfig, cmd: &CargoCmd) {
-    let run_args = match cmd {
-        CargoCmd::Run(run) => run,
-        CargoCmd::Test(test) => &test.run_args,
-        _ => unreachable!(),
+/// Start GDB with a script loading the ELF executable and connecting to the
+/// remote stub given in the arguments.
+pub fn debug(config: &CTRConfig, debug: &command::Debug) -> Result<(), Error> {
+    let gdb = match UserConfig::load()?.gdb {
+        Some(gdb) => gdb,
+        None => match env::var_os("DEVKITARM") {
+            Some(devkitarm) => Path::new(&devkitarm).join("bin").join(gdb::DEFAULT_GDB),
+            None => PathBuf::from(gdb::DEFAULT_GDB),
+        },
     };
 
-    let mut process = Command::new("3dslink")
-        .arg(config.path_3dsx())
-        .args(run_args.get_3dslink_args())
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .unwrap();
-
-    let status = process.wait().unwrap();
-
-    if !status.success() {
-        process::exit(status.code().unwrap_or(1));
-    }
-}
-
-/// Read the `RomFS` path from the Cargo manifest. If it's unset, use the default.
-/// The returned boolean is true when the default is used.
-pub fn get_romfs_path(config: &CTRConfig) -> (PathBuf, bool) {
-    let manifest_path = &config.cargo_manifest_path;
-    let manifest_str = std::fs::read_to_string(manifest_path)
-        .unwrap_or_else(|e| panic!("Could not open {}: {e}", manifest_path.display()));
-    let manifest_data: toml::Value =
-        toml::de::from_str(&manifest_str).expect("Could not parse Cargo manifest as TOML");
-
-    // Find the romfs setting and compute the path
-    let mut is_default = false;
-    let romfs_dir_setting = manifest_data
-        .as_table()
-        .and_then(|table| table.get("package"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("metadata"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("cargo-3ds"))
-        .and_then(toml::Value::as_table)
-        .and_then(|table| table.get("romfs_dir"))
-        .and_then(toml::Value::as_str)
-        .unwrap_or_else(|| {
-            is_default = true;
-            "romfs"
-        });
-    let mut romfs_path = manifest_path.clone();
-    romfs_path.pop(); // Pop Cargo.toml
-    romfs_path.push(romfs_dir_setting);
+    // The source paths are only a convenience, so debug without them rather
+    // than fail if the toolchain can't be queried.
+    let rust_sysroot = find_sysroot().ok();
+    let rustc_commit_hash = rustc_version::version_meta()
+        .ok()
+        .and_then(|version| version.commit_hash);
+    let devkitarm = env::var_os("DEVKITARM").map(PathBuf::from);
+
+    let session = gdb::Session {
+        elf: &config.target_path,
+        address: &debug.remote,
+        rust_sysroot: rust_sysroot.as_deref(),
+        rustc_commit_hash: rustc_commit_hash.as_deref(),
+        devkitarm: devkitarm.as_deref(),
+    };
+    let script_path = config.target_path.with_extension("gdbinit");
+    std::fs::write(&script_path, session.init_script())
+        .map_err(|e| Error::io("write", &script_path, e))?;
+
+    let status = Command::new(&gdb)
+        .arg("-q")
+        .arg("-x")
+        .arg(&script_path)
+        .status()
+        .map_err(|source| Error::ToolNotFound {
+            tool: gdb.display().to_string(),
+            source,
+        })?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Exited {
+            tool: gdb.display().to_string(),
+            status,
+        })
+    }
+}
+
+/// Print the functions and source lines of code addresses, found with the debug
+/// info of an ELF executable. Without addresses, the lines of stdin are copied
+/// to stdout, each followed by the functions of the addresses in it.
+pub fn addr2line(elf: &Path, addresses: &[u64]) -> Result<(), Error> {
+    let symbolizer = load_symbolizer(elf)?;
+    let symbolize = |address, indent, out: &mut dyn Write| -> Result<(), Error> {
+        let frames = symbolizer
+            .frames(address)
+            .map_err(|source| Error::Symbolize {
+                path: elf.to_path_buf(),
+                source,
+            })?;
+        symbolize::write_frames(out, address, &frames, indent)
+            .map_err(|e| Error::io("write", Path::new("stdout"), e))
+    };
+
+    let mut out = io::stdout().lock();
+    if !addresses.is_empty() {
+        for &address in addresses {
+            symbolize(address, "", &mut out)?;
+        }
+        return Ok(());
+    }
+
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(|e| Error::io("read", Path::new("stdin"), e))?;
+        writeln!(out, "{line}").map_err(|e| Error::io("write", Path::new("stdout"), e))?;
+        for address in symbolize::find_addresses(&line) {
+            symbolize(address, "    ", &mut out)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn load_symbolizer(elf: &Path) -> Result<Symbolizer, Error> {
+    Symbolizer::new(&read_file(elf)?).map_err(|source| Error::Symbolize {
+        path: elf.to_path_buf(),
+        source,
+    })
+}
+
+/// Read a Luma3DS crash dump.
+pub fn read_crash_dump(path: &Path) -> Result<Dump, Error> {
+    Dump::parse(&read_file(path)?).map_err(|source| Error::CrashDump {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Find the built executable that crashed, from the process of the dump. A
+/// 3dsx runs in the process of the Homebrew Launcher, so a single executable is
+/// assumed to be the one that crashed if no title matches.
+pub fn crashed_executable<'a>(dump: &Dump, configs: &'a [CTRConfig]) -> Option<&'a CTRConfig> {
+    let Some(process) = &dump.process else {
+        eprintln!("warning: ARM9 crashes are not symbolized");
+        return None;
+    };
+
+    if let Some(config) = configs.iter().find(|config| {
+        config.metadata.title_id() == Some(process.title_id)
+            || config.name.bytes().take(8).eq(process.name.bytes())
+    }) {
+        return Some(config);
+    }
+
+    match configs {
+        [config] => {
+            eprintln!(
+                "warning: process `{}` is not a title that was built, assuming it ran {}",
+                process.name,
+                config.path_3dsx().display()
+            );
+            Some(config)
+        }
+        _ => {
+            eprintln!(
+                "warning: process `{}` is not a title that was built, use `--elf` to \
+                symbolize its addresses",
+                process.name
+            );
+            None
+        }
+    }
+}
+
+/// Print a readable report of a crash dump, symbolized with the ELF executable
+/// if given.
+pub fn print_crash_report(dump: &Dump, elf: Option<&Path>) -> Result<(), Error> {
+    let symbolizer = elf.map(load_symbolizer).transpose()?;
+    dump.write_report(&mut io::stdout().lock(), symbolizer.as_ref())
+        .map_err(|e| Error::io("write", Path::new("stdout"), e))
+}
+
+/// Get the address of the 3ds from the arguments, or by looking for it on the
+/// local network.
+fn find_device(run_args: &Run) -> Result<Ipv4Addr, Error> {
+    match run_args.address {
+        Some(address) => Ok(address),
+        None => threedslink::discover(run_args.retries.unwrap_or(threedslink::DEFAULT_RETRIES))
+            .map_err(|source| Error::Link {
+                context: String::from("could not find a 3DS"),
+                source,
+            }),
+    }
+}
+
+/// Start the 3dslink server, only accepting connections from the 3ds.
+fn bind_server(address: Ipv4Addr) -> Result<Server, Error> {
+    let server = Server::bind(threedslink::NETLOADER_PORT).map_err(|source| Error::Link {
+        context: String::from("could not start the 3dslink server"),
+        source,
+    })?;
+    server.set_device(address.into());
+    Ok(server)
+}
+
+/// Send the 3dsx to the netloader. When the connection is refused, e.g. while
+/// the Homebrew Launcher restarts after running a previous executable, this is
+/// retried once a second.
+fn send_3dsx(config: &CTRConfig, run_args: &Run, address: Ipv4Addr) -> Result<(), Error> {
+    let path = config.path_3dsx();
+    let mut retries = run_args.retries.unwrap_or(threedslink::DEFAULT_RETRIES);
+
+    loop {
+        let result = threedslink::send(
+            (address, threedslink::NETLOADER_PORT).into(),
+            &path,
+            &run_args.get_argv(&path),
+        );
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused && retries > 0 => {
+                retries -= 1;
+                thread::sleep(Duration::from_secs(1));
+            }
+            Err(source) => {
+                return Err(Error::Link {
+                    context: format!("could not send {} to {address}", path.display()),
+                    source,
+                })
+            }
+        }
+    }
+}
+
+/// Create a project in a new directory with `cargo 3ds new`.
+pub fn new_project(new: &New) -> Result<(), Error> {
+    if new.path.exists() {
+        return Err(Error::Config(format!(
+            "destination {} already exists, use `cargo 3ds init` to create a \
+            project in an existing directory",
+            new.path.display()
+        )));
+    }
+    create_project(&new.path, &new.options)
+}
+
+/// Create a project in an existing directory with `cargo 3ds init`.
+pub fn init_project(init: &Init) -> Result<(), Error> {
+    create_project(&init.path, &init.options)
+}
+
+/// The package is named after the directory, unless a name is given.
+fn create_project(path: &Path, options: &NewOptions) -> Result<(), Error> {
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => {
+            let dir = if path.exists() {
+                path.canonicalize()
+            } else {
+                env::current_dir().map(|cwd| cwd.join(path))
+            }
+            .map_err(|e| Error::io("access", path, e))?;
+
+            match dir.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => {
+                    return Err(Error::Config(format!(
+                        "could not name a package after {}, use `--name` to set one",
+                        path.display()
+                    )))
+                }
+            }
+        }
+    };
+
+    scaffold::create(path, &name, options.template)?;
+    eprintln!("Created package `{name}` in {}", path.display());
+
+    Ok(())
+}
+
+/// Print what a 3DSX, SMDH or CIA file contains, and export its icons.
+pub fn info(args: &Info) -> Result<(), Error> {
+    let info = FileInfo::read(&read_file(&args.file)?).ok_or_else(|| Error::InvalidFile {
+        path: args.file.clone(),
+        expected: "3DSX, SMDH or CIA",
+    })?;
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&info).expect("file info should serialize");
+        println!("{json}");
+    } else {
+        print!("{info}");
+    }
+
+    if let Some(dir) = &args.export_icons {
+        let Some(smdh) = info.smdh() else {
+            return Err(Error::Config(format!(
+                "{} has no SMDH to export icons from",
+                args.file.display()
+            )));
+        };
+        std::fs::create_dir_all(dir).map_err(|e| Error::io("create", dir, e))?;
+
+        let stem = args.file.file_stem().unwrap_or_default().to_string_lossy();
+        for (name, icon) in info::icon_files(smdh, &stem) {
+            let path = dir.join(name);
+            std::fs::write(&path, icon.to_png()).map_err(|e| Error::io("write", &path, e))?;
+            eprintln!("Exported icon {}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// List or extract the files of a built `RomFS` image.
+pub fn romfs(args: &Romfs) -> Result<(), Error> {
+    let (RomfsAction::Ls { artifact } | RomfsAction::Extract { artifact, .. }) = &args.action;
+
+    let data = read_file(artifact)?;
+    let invalid = || Error::InvalidFile {
+        path: artifact.clone(),
+        expected: "3DSX, CIA or RomFS",
+    };
+    let (offset, image) = find_romfs(&data).ok_or_else(invalid)?;
+    let Some(image) = image else {
+        return Err(Error::Config(format!(
+            "{} has no RomFS",
+            artifact.display()
+        )));
+    };
+    let entries = romfs::read_entries(image).ok_or_else(invalid)?;
+
+    match &args.action {
+        RomfsAction::Ls { .. } => {
+            for entry in &entries {
+                match entry {
+                    romfs::Entry::Dir { path } => println!("{:>10} {:>10}  {path}/", "", ""),
+                    romfs::Entry::File {
+                        path,
+                        offset: file_offset,
+                        size,
+                    } => {
+                        let file_offset = offset as u64 + file_offset;
+                        println!("{file_offset:#010x} {size:>10}  {path}");
+                    }
+                }
+            }
+        }
+        RomfsAction::Extract { dir, .. } => {
+            std::fs::create_dir_all(dir).map_err(|e| Error::io("create", dir, e))?;
 
-    (romfs_path, is_default)
+            let mut files = 0;
+            for entry in &entries {
+                match entry {
+                    romfs::Entry::Dir { path } => {
+                        let path = dir.join(path);
+                        std::fs::create_dir_all(&path)
+                            .map_err(|e| Error::io("create", &path, e))?;
+                    }
+                    romfs::Entry::File { path, offset, size } => {
+                        let contents = &image[*offset as usize..][..*size as usize];
+                        let path = dir.join(path);
+                        std::fs::write(&path, contents)
+                            .map_err(|e| Error::io("write", &path, e))?;
+                        files += 1;
+                    }
+                }
+            }
+            eprintln!("Extracted {files} files into {}", dir.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the level 3 `RomFS` image of a 3DSX, CIA or `RomFS` file, with its
+/// offset in the file. The image is `None` if the 3DSX or CIA has no `RomFS`.
+fn find_romfs(data: &[u8]) -> Option<(usize, Option<&[u8]>)> {
+    let image = if let Some(header) = threedsx::Header::parse(data) {
+        match header.romfs_offset {
+            Some(offset) => Some(data.get(offset as usize..)?),
+            None => None,
+        }
+    } else if let Some(cia) = cia::Cia::parse(data) {
+        let partition = cia.contents.first()?.data;
+        let header = ncch::Header::parse(partition)?;
+        match header.romfs_size {
+            0 => None,
+            _ => Some(ncch::ivfc_level3(
+                partition.get(header.romfs_offset as usize..)?,
+            )?),
+        }
+    } else {
+        // Either the IVFC-wrapped image of an NCCH partition, or a bare one.
+        Some(ncch::ivfc_level3(data).unwrap_or(data))
+    };
+
+    // All of the images are slices of the file.
+    let offset = image.map_or(0, |image| image.as_ptr() as usize - data.as_ptr() as usize);
+    Some((offset, image))
+}
+
+/// Print the report of `cargo 3ds doctor`. Returns an error if any check failed.
+pub fn doctor() -> Result<(), Error> {
+    let checks = doctor::run_checks();
+    for check in &checks {
+        println!("{check}");
+    }
+
+    let errors = checks
+        .iter()
+        .filter(|check| check.status == doctor::Status::Error)
+        .count();
+    match errors {
+        0 => Ok(()),
+        1 => Err(Error::Config(String::from("1 problem found"))),
+        _ => Err(Error::Config(format!("{errors} problems found"))),
+    }
+}
+
+/// Get the `RomFS` directory from the package metadata, or the default `romfs`
+/// directory if it's unset. Returns `None` when the default directory does not
+/// exist, and an error when the configured one does not.
+pub fn get_romfs_path(config: &CTRConfig) -> Result<Option<PathBuf>, Error> {
+    let romfs_path = match &config.metadata.romfs_dir {
+        Some(romfs_dir) => config.manifest_dir().join(romfs_dir),
+        None => config.manifest_dir().join("romfs"),
+    };
+
+    if romfs_path.is_dir() {
+        Ok(Some(romfs_path))
+    } else if config.metadata.romfs_dir.is_none() {
+        Ok(None)
+    } else {
+        Err(Error::Config(format!(
+            "could not find configured RomFS dir: {}",
+            romfs_path.display()
+        )))
+    }
 }
 
 #[derive(Deserialize, Default)]
@@ -345,12 +1123,18 @@ pub struct CTRConfig {
     name: String,
     author: String,
     description: String,
-    icon: String,
+    icon: PathBuf,
     target_path: PathBuf,
     cargo_manifest_path: PathBuf,
+    metadata: Metadata,
 }
 
 impl CTRConfig {
+    /// The ELF executable built by cargo.
+    pub fn path_elf(&self) -> &Path {
+        &self.target_path
+    }
+
     pub fn path_3dsx(&self) -> PathBuf {
         self.target_path.with_extension("3dsx")
     }
@@ -358,6 +1142,32 @@ impl CTRConfig {
     pub fn path_smdh(&self) -> PathBuf {
         self.target_path.with_extension("smdh")
     }
+
+    pub fn path_romfs(&self) -> PathBuf {
+        self.target_path.with_extension("romfs")
+    }
+
+    pub fn path_cia(&self) -> PathBuf {
+        self.target_path.with_extension("cia")
+    }
+
+    pub fn path_cci(&self) -> PathBuf {
+        self.target_path.with_extension("3ds")
+    }
+
+    /// The title ID of installable builds, which must be configured.
+    fn title_id(&self) -> Result<u64, Error> {
+        self.metadata.title_id().ok_or_else(|| {
+            Error::Config(String::from(
+                "building a CIA or CCI requires a `unique_id` or `title_id` in \
+                [package.metadata.cargo-3ds]",
+            ))
+        })
+    }
+
+    fn manifest_dir(&self) -> &Path {
+        self.cargo_manifest_path.parent().unwrap()
+    }
 }
 
 #[derive(Ord, PartialOrd, PartialEq, Eq, Debug)]
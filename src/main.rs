@@ -1,44 +1,233 @@
-use cargo_3ds::command::Cargo;
-use cargo_3ds::{build_3dsx, build_smdh, check_rust_version, get_metadata, link, run_cargo};
+use cargo_3ds::command::{Addr2line, Cargo, CargoCmd, Crash};
+use cargo_3ds::watch::Watcher;
+use cargo_3ds::{
+    addr2line, build_3dsx, build_cci, build_cia, build_ncch, build_romfs, build_smdh,
+    check_rust_version, crashed_executable, debug, doctor, get_metadata, info, init_project, link,
+    new_project, print_crash_report, read_crash_dump, romfs, run_cargo, run_in_emulator, run_tests,
+    CTRConfig, Error, Linker,
+};
 
 use clap::Parser;
 
 use std::process;
 
 fn main() {
-    check_rust_version();
+    if let Err(err) = run() {
+        // Tools forwarding their exit status have already reported their errors.
+        if !matches!(err, Error::Exited { .. }) {
+            eprintln!("error: {err}");
+        }
+        process::exit(match err {
+            // Like `cargo test`, which exits with the code of the failed test harness.
+            Error::TestsFailed => 101,
+            Error::Exited { status, .. } => status.code().unwrap_or(1),
+            _ => 1,
+        });
+    }
+}
 
+fn run() -> Result<(), Error> {
     let Cargo::Input(mut input) = Cargo::parse();
 
-    let message_format = match input.cmd.extract_message_format() {
-        Ok(fmt) => fmt,
-        Err(msg) => {
-            eprintln!("{msg}");
-            process::exit(1)
-        }
-    };
+    match &input.cmd {
+        CargoCmd::New(new) => return new_project(new),
+        CargoCmd::Init(init) => return init_project(init),
+        CargoCmd::Doctor => return doctor(),
+        CargoCmd::Info(args) => return info(args),
+        CargoCmd::Romfs(args) => return romfs(args),
+        _ => {}
+    }
+
+    // Nothing is built when symbolizing with a given executable.
+    if let CargoCmd::Addr2line(Addr2line {
+        elf: Some(elf),
+        addresses,
+        ..
+    }) = &input.cmd
+    {
+        return addr2line(elf, addresses);
+    }
+    if let CargoCmd::Crash(Crash {
+        dump,
+        elf: Some(elf),
+        ..
+    }) = &input.cmd
+    {
+        return print_crash_report(&read_crash_dump(dump)?, Some(elf));
+    }
+
+    check_rust_version()?;
+
+    let message_format = input.cmd.extract_message_format()?;
+
+    if let CargoCmd::Addr2line(args) = &input.cmd {
+        return match built_executables(&input.cmd, &message_format)?.as_slice() {
+            [app_conf] => addr2line(app_conf.path_elf(), &args.addresses),
+            app_confs => Err(ambiguous(app_confs)),
+        };
+    }
+    if let CargoCmd::Crash(args) = &input.cmd {
+        // Check the dump before building.
+        let dump = read_crash_dump(&args.dump)?;
+        let app_confs = built_executables(&input.cmd, &message_format)?;
+        let elf = crashed_executable(&dump, &app_confs).map(CTRConfig::path_elf);
+        return print_crash_report(&dump, elf);
+    }
+
+    if input.cmd.should_watch() {
+        return watch(&input.cmd, &message_format);
+    }
 
-    let (status, messages) = run_cargo(&input.cmd, message_format);
+    let app_confs = build(&input.cmd, &message_format)?;
+    run_executables(&input.cmd, &app_confs)
+}
+
+/// Build the executables with cargo, and the 3DS files requested from them.
+fn build(cmd: &CargoCmd, message_format: &Option<String>) -> Result<Vec<CTRConfig>, Error> {
+    let (status, messages) = run_cargo(cmd, message_format.clone())?;
 
     if !status.success() {
-        process::exit(status.code().unwrap_or(1));
+        return Err(Error::Exited {
+            tool: String::from("cargo"),
+            status,
+        });
     }
 
-    if !input.cmd.should_build_3dsx() {
-        return;
+    if !cmd.should_build_3dsx() {
+        return Ok(Vec::new());
     }
 
     eprintln!("Getting metadata");
-    let app_conf = get_metadata(&messages);
+    let app_confs = get_metadata(&messages)?;
+
+    let runs_one = matches!(cmd, CargoCmd::Run(_))
+        && (cmd.should_link_to_device() || cmd.should_run_in_emulator());
+    if (runs_one || matches!(cmd, CargoCmd::Debug(_))) && app_confs.len() > 1 {
+        return Err(ambiguous(&app_confs));
+    }
+
+    for app_conf in &app_confs {
+        eprintln!("Building smdh:{}", app_conf.path_smdh().display());
+        build_smdh(app_conf)?;
+
+        let romfs = build_romfs(app_conf)?;
+
+        eprintln!("Building 3dsx: {}", app_conf.path_3dsx().display());
+        build_3dsx(app_conf, romfs.as_ref())?;
+
+        if cmd.should_build_cia() || cmd.should_build_cci() {
+            eprintln!("Building ncch");
+            let partition = build_ncch(app_conf, romfs.as_ref())?;
+
+            if cmd.should_build_cia() {
+                eprintln!("Building cia: {}", app_conf.path_cia().display());
+                build_cia(app_conf, &partition)?;
+            }
+
+            if cmd.should_build_cci() {
+                eprintln!("Building cci: {}", app_conf.path_cci().display());
+                build_cci(app_conf, &partition)?;
+            }
+        }
+    }
+
+    Ok(app_confs)
+}
+
+/// Build the executables with cargo, without any 3DS file.
+fn built_executables(
+    cmd: &CargoCmd,
+    message_format: &Option<String>,
+) -> Result<Vec<CTRConfig>, Error> {
+    let (status, messages) = run_cargo(cmd, message_format.clone())?;
+
+    if !status.success() {
+        return Err(Error::Exited {
+            tool: String::from("cargo"),
+            status,
+        });
+    }
+
+    get_metadata(&messages)
+}
+
+fn ambiguous(app_confs: &[CTRConfig]) -> Error {
+    let paths: Vec<_> = app_confs
+        .iter()
+        .map(|app_conf| format!("  {}", app_conf.path_elf().display()))
+        .collect();
+
+    Error::Config(format!(
+        "several executables were built, so the one to use is ambiguous:\n{}\n\
+        Use `--bin`, `--example` or `--package` to pick one of them.",
+        paths.join("\n")
+    ))
+}
 
-    eprintln!("Building smdh:{}", app_conf.path_smdh().display());
-    build_smdh(&app_conf);
+/// Run the built executables, on a device, in an emulator or in GDB.
+fn run_executables(cmd: &CargoCmd, app_confs: &[CTRConfig]) -> Result<(), Error> {
+    let run_args = match cmd {
+        CargoCmd::Debug(args) => {
+            eprintln!("Running gdb");
+            return debug(&app_confs[0], args);
+        }
+        CargoCmd::Run(run) => run,
+        CargoCmd::Test(test) => &test.run_args,
+        _ => return Ok(()),
+    };
+
+    if cmd.should_run_in_emulator() {
+        run_in_emulator(app_confs, run_args)?;
+    }
+
+    if cmd.should_link_to_device() {
+        if let CargoCmd::Test(test) = cmd {
+            run_tests(app_confs, test)?;
+        } else {
+            eprintln!("Running 3dslink");
+            link(&app_confs[0], run_args)?;
+        }
+    }
 
-    eprintln!("Building 3dsx: {}", app_conf.path_3dsx().display());
-    build_3dsx(&app_conf);
+    Ok(())
+}
+
+/// Build and run the executables each time the sources change, until interrupted.
+/// Errors are reported without stopping, except those of the watcher itself.
+fn watch(cmd: &CargoCmd, message_format: &Option<String>) -> Result<(), Error> {
+    let mut watcher = Watcher::for_workspace()?;
+    let mut linker = None;
+
+    loop {
+        let result = build(cmd, message_format).and_then(|app_confs| {
+            watcher.watch_assets(&app_confs)?;
+
+            // Keep the 3dslink server running between runs, instead of
+            // serving until interrupted after the first one.
+            match cmd {
+                CargoCmd::Run(run_args) if cmd.should_link_to_device() => {
+                    let linker = match &mut linker {
+                        Some(linker) => linker,
+                        None => linker.insert(Linker::start(run_args)?),
+                    };
+                    eprintln!("Running 3dslink");
+                    linker.send(&app_confs[0], run_args)
+                }
+                _ => run_executables(cmd, &app_confs),
+            }
+        });
+
+        match result {
+            Ok(()) => {}
+            Err(Error::Watch(err)) => return Err(Error::Watch(err)),
+            Err(Error::Exited { tool, status }) if tool == "cargo" => {
+                eprintln!("error: build failed with {status}");
+            }
+            Err(err) => eprintln!("error: {err}"),
+        }
 
-    if input.cmd.should_link_to_device() {
-        eprintln!("Running 3dslink");
-        link(&app_conf, &input.cmd);
+        eprintln!("Waiting for changes (press Ctrl-C to stop)");
+        let changed = watcher.wait_for_change()?;
+        eprintln!("{} changed, rebuilding", changed.display());
     }
 }
@@ -0,0 +1,575 @@
+//! Settings read from the `[package.metadata.cargo-3ds]` table of a package
+//! manifest.
+
+use crate::emulator::EmulatorCommand;
+use crate::ncsd::{CardType, MediaSize};
+use crate::smdh::{Language, Title};
+
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The high half of the title ID of applications installed to the SD card.
+const APPLICATION_TITLE_ID: u64 = 0x0004_0000_0000_0000;
+
+/// The highest unique ID, which takes 20 bits of the title ID.
+const MAX_UNIQUE_ID: u32 = 0xF_FFFF;
+
+/// The product code used by makerom when none is given.
+const DEFAULT_PRODUCT_CODE: &str = "CTR-P-CTAP";
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Metadata {
+    /// Name of the application, either as a string or in the `name` key of
+    /// a table which also holds the titles of specific languages.
+    pub title: Titles,
+
+    /// Text appended to the name of the application, e.g. `" (debug)"`.
+    pub title_suffix: Option<String>,
+
+    /// Publisher shown in the home menu, instead of the first package author.
+    pub publisher: Option<String>,
+
+    /// Description shown in the home menu, instead of the package description.
+    pub description: Option<String>,
+
+    /// Icon shown in the home menu, relative to the manifest. It can be a
+    /// PNG, JPEG or GIF image of any size.
+    pub icon: Option<PathBuf>,
+
+    /// Directory to build the `RomFS` from, relative to the manifest.
+    pub romfs_dir: Option<PathBuf>,
+
+    /// Full title ID of installable builds. Takes precedence over `unique_id`.
+    pub title_id: Option<u64>,
+
+    /// Unique ID of installable builds, from which an application title ID
+    /// is made.
+    pub unique_id: Option<u32>,
+
+    /// Product code of installable builds, e.g. `CTR-P-ABCD`.
+    pub product_code: Option<String>,
+
+    /// Banner shown on the HOME Menu for installable builds, relative to the
+    /// manifest. This must already be in the `.bnr` format.
+    pub banner: Option<PathBuf>,
+
+    /// Capacity of the card of cartridge images, e.g. `"512MB"`. Defaults to
+    /// the smallest one that fits.
+    pub media_size: Option<MediaSize>,
+
+    /// Kind of card of cartridge images, `"card1"` or `"card2"`.
+    pub card_type: Option<CardType>,
+
+    /// Emulator used by `cargo 3ds run --emulator`, as a program or a list of
+    /// the program and its arguments. Paths are relative to the manifest.
+    pub emulator: Option<EmulatorCommand>,
+
+    /// Settings of specific binaries, from `bin.<name>` tables.
+    pub bin: BTreeMap<String, TargetMetadata>,
+
+    /// Settings of specific examples, from `example.<name>` tables.
+    pub example: BTreeMap<String, TargetMetadata>,
+
+    /// Settings of builds with specific profiles, from `profile.<name>`
+    /// tables. The `dev` profile can also be named `debug`.
+    pub profile: BTreeMap<String, TargetMetadata>,
+}
+
+/// Settings overriding those of the package for one of its targets or
+/// profiles.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct TargetMetadata {
+    /// Name of the target, and its titles for specific languages.
+    pub title: Titles,
+
+    /// Text appended to the name of the target.
+    pub title_suffix: Option<String>,
+
+    /// Description of the target.
+    pub description: Option<String>,
+
+    /// Icon of the target, relative to the manifest.
+    pub icon: Option<PathBuf>,
+
+    /// Directory to build the `RomFS` of the target from, relative to the
+    /// manifest.
+    pub romfs_dir: Option<PathBuf>,
+}
+
+/// The `title` setting.
+#[derive(Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "TitlesRepr")]
+pub struct Titles {
+    /// Name of the application, instead of the name of the target.
+    pub name: Option<String>,
+
+    /// Titles shown for specific languages, from `title.<language>` tables.
+    /// Languages that are not listed use the default title.
+    pub languages: BTreeMap<Language, LocalizedTitle>,
+}
+
+/// The forms the `title` setting can be written in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TitlesRepr {
+    Name(String),
+    Table {
+        name: Option<String>,
+        #[serde(flatten)]
+        languages: BTreeMap<Language, LocalizedTitle>,
+    },
+}
+
+impl Titles {
+    /// Override these titles with those that are set in `other`.
+    pub fn merge(&mut self, other: Titles) {
+        if other.name.is_some() {
+            self.name = other.name;
+        }
+        self.languages.extend(other.languages);
+    }
+}
+
+impl From<TitlesRepr> for Titles {
+    fn from(repr: TitlesRepr) -> Self {
+        match repr {
+            TitlesRepr::Name(name) => Self {
+                name: Some(name),
+                languages: BTreeMap::new(),
+            },
+            TitlesRepr::Table { name, languages } => Self { name, languages },
+        }
+    }
+}
+
+/// The strings shown for one language. Those that are unset are taken from
+/// the default title.
+#[derive(Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(default)]
+pub struct LocalizedTitle {
+    pub short_description: Option<String>,
+    pub long_description: Option<String>,
+    pub publisher: Option<String>,
+}
+
+impl LocalizedTitle {
+    /// Fill in the unset strings from `default`.
+    pub fn or(&self, default: &Title) -> Title {
+        Title {
+            short_description: self
+                .short_description
+                .clone()
+                .unwrap_or_else(|| default.short_description.clone()),
+            long_description: self
+                .long_description
+                .clone()
+                .unwrap_or_else(|| default.long_description.clone()),
+            publisher: self
+                .publisher
+                .clone()
+                .unwrap_or_else(|| default.publisher.clone()),
+        }
+    }
+}
+
+impl Metadata {
+    /// Read the `cargo-3ds` table from the `metadata` of a package or
+    /// workspace, as given by `cargo metadata`.
+    pub fn from_package_metadata(metadata: &serde_json::Value) -> Result<Self, serde_json::Error> {
+        match metadata.get("cargo-3ds") {
+            Some(table) => Self::deserialize(table),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Check that the unique ID fits in a title ID, since the settings can
+    /// hold values that cannot be used.
+    pub fn check_unique_id(&self) -> Result<(), String> {
+        match self.unique_id {
+            Some(unique_id) if unique_id > MAX_UNIQUE_ID => Err(format!(
+                "`unique_id` {unique_id:#x} is out of range, the highest one is {MAX_UNIQUE_ID:#x}"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Use the settings of `workspace` for those that are not set here.
+    /// Titles and target tables are merged, with these taking precedence.
+    pub fn inherit(self, workspace: Metadata) -> Self {
+        let mut title = workspace.title;
+        title.merge(self.title);
+        let mut bin = workspace.bin;
+        bin.extend(self.bin);
+        let mut example = workspace.example;
+        example.extend(self.example);
+        let mut profile = workspace.profile;
+        profile.extend(self.profile);
+
+        Self {
+            title,
+            title_suffix: self.title_suffix.or(workspace.title_suffix),
+            publisher: self.publisher.or(workspace.publisher),
+            description: self.description.or(workspace.description),
+            icon: self.icon.or(workspace.icon),
+            romfs_dir: self.romfs_dir.or(workspace.romfs_dir),
+            title_id: self.title_id.or(workspace.title_id),
+            unique_id: self.unique_id.or(workspace.unique_id),
+            product_code: self.product_code.or(workspace.product_code),
+            banner: self.banner.or(workspace.banner),
+            media_size: self.media_size.or(workspace.media_size),
+            card_type: self.card_type.or(workspace.card_type),
+            emulator: self.emulator.or(workspace.emulator),
+            bin,
+            example,
+            profile,
+        }
+    }
+
+    /// Make the paths of the settings relative to `base` instead, e.g. to
+    /// resolve the paths of workspace settings from the workspace root.
+    pub fn rebase_paths(&mut self, base: &Path) {
+        let targets = self
+            .bin
+            .values_mut()
+            .chain(self.example.values_mut())
+            .chain(self.profile.values_mut());
+        let paths = targets
+            .flat_map(|target| [&mut target.icon, &mut target.romfs_dir])
+            .chain([&mut self.icon, &mut self.romfs_dir, &mut self.banner]);
+
+        for path in paths.flatten() {
+            *path = base.join(&*path);
+        }
+        if let Some(emulator) = &mut self.emulator {
+            emulator.rebase(base);
+        }
+    }
+
+    /// The title shown for languages without their own, given the heuristic
+    /// name, description and authors of the target.
+    pub fn default_title(&self, name: &str, description: &str, authors: &[String]) -> Title {
+        Title {
+            short_description: self.with_suffix(self.title.name.as_deref().unwrap_or(name)),
+            long_description: self
+                .description
+                .clone()
+                .unwrap_or_else(|| description.to_string()),
+            publisher: self.publisher.clone().unwrap_or_else(|| match authors {
+                [author, ..] => author_name(author).to_string(),
+                [] => String::from("Unspecified Author"), // as standard with the devkitPRO toolchain
+            }),
+        }
+    }
+
+    /// The titles of specific languages, given the default title. Their names
+    /// get the title suffix too.
+    pub fn localized_titles(&self, default: &Title) -> Vec<(Language, Title)> {
+        self.title
+            .languages
+            .iter()
+            .map(|(&language, localized)| {
+                let mut title = localized.or(default);
+                if localized.short_description.is_some() {
+                    title.short_description = self.with_suffix(&title.short_description);
+                }
+                (language, title)
+            })
+            .collect()
+    }
+
+    fn with_suffix(&self, name: &str) -> String {
+        name.to_string() + self.title_suffix.as_deref().unwrap_or_default()
+    }
+
+    /// The settings of a target, given its kind (e.g. `bin`) and name, with
+    /// those of its `bin.<name>` or `example.<name>` table applied.
+    pub fn for_target(self, kind: &str, name: &str) -> Self {
+        match self.target(kind, name).cloned() {
+            Some(target) => self.with_overrides(target),
+            None => self,
+        }
+    }
+
+    /// The settings of a build with the given profile, with those of its
+    /// `profile.<name>` table applied.
+    pub fn for_profile(self, profile: &str) -> Self {
+        let overrides = match self.profile.get(profile) {
+            None if profile == "dev" => self.profile.get("debug"),
+            overrides => overrides,
+        };
+
+        match overrides.cloned() {
+            Some(overrides) => self.with_overrides(overrides),
+            None => self,
+        }
+    }
+
+    fn with_overrides(mut self, overrides: TargetMetadata) -> Self {
+        self.title.merge(overrides.title);
+        self.title_suffix = overrides.title_suffix.or(self.title_suffix);
+        self.description = overrides.description.or(self.description);
+        self.icon = overrides.icon.or(self.icon);
+        self.romfs_dir = overrides.romfs_dir.or(self.romfs_dir);
+
+        self
+    }
+
+    /// The settings of a target, given its kind (e.g. `bin`) and name.
+    pub fn target(&self, kind: &str, name: &str) -> Option<&TargetMetadata> {
+        match kind {
+            "bin" => self.bin.get(name),
+            "example" => self.example.get(name),
+            _ => None,
+        }
+    }
+
+    /// The title ID of installable builds, if one is configured.
+    pub fn title_id(&self) -> Option<u64> {
+        self.title_id.or_else(|| {
+            self.unique_id
+                .map(|unique_id| APPLICATION_TITLE_ID | u64::from(unique_id) << 8)
+        })
+    }
+
+    /// The product code of installable builds.
+    pub fn product_code(&self) -> &str {
+        self.product_code.as_deref().unwrap_or(DEFAULT_PRODUCT_CODE)
+    }
+}
+
+/// Strip the email address from an author in the `Name <email>` form.
+fn author_name(author: &str) -> &str {
+    match author.split_once('<') {
+        Some((name, email)) if email.ends_with('>') && !name.trim().is_empty() => name.trim(),
+        _ => author.trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_id() {
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": { "unique_id": 1045503, "romfs_dir": "assets", "media_size": "1GB" } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+
+        assert_eq!(metadata.romfs_dir, Some(PathBuf::from("assets")));
+        assert_eq!(metadata.title_id(), Some(0x0004_0000_0FF3_FF00));
+        assert_eq!(metadata.product_code(), DEFAULT_PRODUCT_CODE);
+        assert_eq!(metadata.media_size, Some(MediaSize::Gb1));
+
+        let metadata = Metadata {
+            title_id: Some(0x0004_0000_0012_3400),
+            ..metadata
+        };
+        assert_eq!(metadata.title_id(), Some(0x0004_0000_0012_3400));
+
+        assert_eq!(metadata.check_unique_id(), Ok(()));
+        let metadata = Metadata {
+            unique_id: Some(0x10_0000),
+            ..metadata
+        };
+        assert!(metadata.check_unique_id().is_err());
+
+        let none = Metadata::from_package_metadata(&serde_json::Value::Null).unwrap();
+        assert_eq!(none.title_id(), None);
+    }
+
+    #[test]
+    fn localized_titles() {
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": { "title": {
+                "ja": { "short_description": "こんにちは", "publisher": "フェリス" },
+                "zh-TW": { "long_description": "測試" }
+            } } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+
+        let default = Title {
+            short_description: String::from("Hello"),
+            long_description: String::from("A test app"),
+            publisher: String::from("Ferris"),
+        };
+        let languages: Vec<_> = metadata.title.languages.keys().copied().collect();
+        assert_eq!(
+            languages,
+            [Language::Japanese, Language::TraditionalChinese]
+        );
+
+        let japanese = metadata.title.languages[&Language::Japanese].or(&default);
+        assert_eq!(japanese.short_description, "こんにちは");
+        assert_eq!(japanese.long_description, "A test app");
+        assert_eq!(japanese.publisher, "フェリス");
+    }
+
+    #[test]
+    fn title_settings() {
+        let authors = [String::from("Ferris <ferris@example.com>")];
+
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": { "title": "Hello", "publisher": "Crabs Inc." } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+        let title = metadata.default_title("hello-world", "A test app", &authors);
+        assert_eq!(title.short_description, "Hello");
+        assert_eq!(title.long_description, "A test app");
+        assert_eq!(title.publisher, "Crabs Inc.");
+
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": {
+                "title": { "name": "Hello", "fr": { "short_description": "Bonjour" } },
+                "description": "Says hello"
+            } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+        assert_eq!(metadata.title.languages.len(), 1);
+        let title = metadata.default_title("hello-world", "A test app", &authors);
+        assert_eq!(title.short_description, "Hello");
+        assert_eq!(title.long_description, "Says hello");
+        assert_eq!(title.publisher, "Ferris");
+
+        let title = Metadata::default().default_title("hello-world", "", &[]);
+        assert_eq!(title.short_description, "hello-world");
+        assert_eq!(title.publisher, "Unspecified Author");
+    }
+
+    #[test]
+    fn author_names() {
+        assert_eq!(author_name("Ferris <ferris@example.com>"), "Ferris");
+        assert_eq!(author_name("Ferris the Crab"), "Ferris the Crab");
+        assert_eq!(author_name("<ferris@example.com>"), "<ferris@example.com>");
+    }
+
+    #[test]
+    fn workspace_inheritance() {
+        let workspace: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": {
+                "publisher": "Crabs Inc.",
+                "icon": "assets/icon.png",
+                "title": { "ja": { "publisher": "カニ" } },
+                "bin": { "foo": { "romfs_dir": "foo/romfs" } }
+            } }"#,
+        )
+        .unwrap();
+        let mut workspace = Metadata::from_package_metadata(&workspace).unwrap();
+        workspace.rebase_paths(Path::new("/workspace"));
+        assert_eq!(
+            workspace.bin["foo"].romfs_dir,
+            Some(PathBuf::from("/workspace/foo/romfs"))
+        );
+
+        let package: serde_json::Value =
+            serde_json::from_str(r#"{ "cargo-3ds": { "title": "Hello", "publisher": "Ferris" } }"#)
+                .unwrap();
+        let metadata = Metadata::from_package_metadata(&package)
+            .unwrap()
+            .inherit(workspace);
+
+        assert_eq!(metadata.title.name.as_deref(), Some("Hello"));
+        assert_eq!(metadata.title.languages.len(), 1);
+        assert_eq!(metadata.publisher.as_deref(), Some("Ferris"));
+        assert_eq!(
+            metadata.icon,
+            Some(PathBuf::from("/workspace/assets/icon.png"))
+        );
+        assert!(metadata.bin.contains_key("foo"));
+
+        // Absolute paths are kept when resolving them from the package.
+        assert_eq!(
+            Path::new("/workspace/app").join(metadata.icon.unwrap()),
+            Path::new("/workspace/assets/icon.png")
+        );
+    }
+
+    #[test]
+    fn profile_overrides() {
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": {
+                "title": { "name": "Hello", "fr": { "short_description": "Bonjour" } },
+                "romfs_dir": "romfs",
+                "profile": { "debug": {
+                    "title_suffix": " (debug)",
+                    "icon": "debug-icon.png",
+                    "romfs_dir": "debug-romfs"
+                } }
+            } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+
+        let release = metadata.clone().for_profile("release");
+        assert_eq!(release.romfs_dir, Some(PathBuf::from("romfs")));
+
+        let debug = metadata.for_profile("dev");
+        assert_eq!(debug.icon, Some(PathBuf::from("debug-icon.png")));
+        assert_eq!(debug.romfs_dir, Some(PathBuf::from("debug-romfs")));
+
+        let title = debug.default_title("hello-world", "", &[]);
+        assert_eq!(title.short_description, "Hello (debug)");
+        let localized = debug.localized_titles(&title);
+        assert_eq!(localized[0].1.short_description, "Bonjour (debug)");
+    }
+
+    #[test]
+    fn target_overrides() {
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": {
+                "icon": "icon.jpg",
+                "bin": { "foo": { "icon": "foo.png" } },
+                "example": { "bar": { "icon": "bar.gif" } }
+            } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+
+        assert_eq!(metadata.icon, Some(PathBuf::from("icon.jpg")));
+        let icon = |kind, name| {
+            metadata
+                .target(kind, name)
+                .and_then(|target| target.icon.clone())
+        };
+        assert_eq!(icon("bin", "foo"), Some(PathBuf::from("foo.png")));
+        assert_eq!(icon("example", "bar"), Some(PathBuf::from("bar.gif")));
+        assert_eq!(icon("example", "foo"), None);
+        assert_eq!(icon("test", "foo"), None);
+    }
+
+    #[test]
+    fn apply_target_overrides() {
+        let package: serde_json::Value = serde_json::from_str(
+            r#"{ "cargo-3ds": {
+                "title": { "name": "Package", "ja": { "short_description": "パッケージ" } },
+                "description": "A package",
+                "icon": "icon.png",
+                "example": { "bar": {
+                    "title": { "name": "Bar", "fr": { "short_description": "Barre" } },
+                    "romfs_dir": "examples/romfs"
+                } }
+            } }"#,
+        )
+        .unwrap();
+        let metadata = Metadata::from_package_metadata(&package).unwrap();
+
+        let bar = metadata.clone().for_target("example", "bar");
+        assert_eq!(bar.title.name.as_deref(), Some("Bar"));
+        assert_eq!(bar.title.languages.len(), 2);
+        assert_eq!(bar.description.as_deref(), Some("A package"));
+        assert_eq!(bar.icon, Some(PathBuf::from("icon.png")));
+        assert_eq!(bar.romfs_dir, Some(PathBuf::from("examples/romfs")));
+
+        let foo = metadata.for_target("bin", "foo");
+        assert_eq!(foo.title.name.as_deref(), Some("Package"));
+        assert_eq!(foo.romfs_dir, None);
+    }
+}
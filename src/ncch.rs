@@ -0,0 +1,590 @@
+//! Building of NCCH partitions, the container used for the executables of
+//! installable titles and cartridge images.
+//!
+//! An NCCH holds an extended header describing the process to create, an
+//! ExeFS with the code and icon, and the `RomFS` wrapped in an IVFC hash tree.
+//! Partitions are neither encrypted nor signed, so installing them requires
+//! signature patches. See <https://www.3dbrew.org/wiki/NCCH>.
+
+use crate::elf::{self, Elf};
+use crate::romfs::RomFs;
+use crate::threedsx::{align, PAGE_SIZE};
+
+use core::fmt;
+use sha2::{Digest, Sha256};
+use std::io;
+
+/// The unit of the offsets and sizes in NCCH and NCSD headers.
+pub const MEDIA_UNIT: usize = 0x200;
+
+/// Address where the code of applications is loaded.
+pub const CODE_ADDRESS: u32 = 0x0010_0000;
+
+/// Kernel core version required by the title.
+pub const CORE_VERSION: u32 = 2;
+
+/// System modules used by libctru applications, listed in the extended
+/// header and in the meta of a CIA.
+pub const DEPENDENCIES: [u64; 22] = [
+    0x0004_0130_0000_2402, // ac
+    0x0004_0130_0000_1502, // am
+    0x0004_0130_0000_3402, // boss
+    0x0004_0130_0000_1602, // camera
+    0x0004_0130_0000_2602, // cecd
+    0x0004_0130_0000_1702, // cfg
+    0x0004_0130_0000_1802, // codec
+    0x0004_0130_0000_2702, // csnd
+    0x0004_0130_0000_2802, // dlp
+    0x0004_0130_0000_1A02, // dsp
+    0x0004_0130_0000_3202, // friends
+    0x0004_0130_0000_1C02, // gsp
+    0x0004_0130_0000_1D02, // hid
+    0x0004_0130_0000_2902, // http
+    0x0004_0130_0000_3302, // ir
+    0x0004_0130_0000_2002, // mic
+    0x0004_0130_0000_2B02, // ndm
+    0x0004_0130_0000_3502, // news
+    0x0004_0130_0000_2D02, // nwm
+    0x0004_0130_0000_2202, // ptm
+    0x0004_0130_0000_2E02, // socket
+    0x0004_0130_0000_2F02, // ssl
+];
+
+/// Services that libctru applications may use.
+const SERVICES: [&str; 30] = [
+    "APT:U", "ac:u", "am:net", "boss:U", "cam:u", "cecd:u", "cfg:nor", "cfg:u", "csnd:SND",
+    "dsp::DSP", "frd:u", "fs:USER", "gsp::Gpu", "gsp::Lcd", "hid:USER", "http:C", "ir:rst", "ir:u",
+    "ir:USER", "mic:u", "ndm:u", "news:s", "nwm::EXT", "nwm::UDS", "ptm:sysm", "ptm:u", "pxi:dev",
+    "soc:U", "ssl:C", "y2r:u",
+];
+
+/// Highest system call number allowed.
+const MAX_SVC: u32 = 0x7D;
+
+const HEADER_SIZE: usize = 0x200;
+/// Size of the hashed part of the extended header.
+const EXHEADER_SIZE: usize = 0x400;
+/// Size of the access descriptor following the extended header.
+const ACCESS_DESC_SIZE: usize = 0x400;
+const STACK_SIZE: u32 = 0x4_0000;
+const EXEFS_HEADER_SIZE: usize = 0x200;
+const IVFC_BLOCK_SIZE: usize = 0x1000;
+const IVFC_HEADER_SIZE: usize = 0x5C;
+
+/// The contents of an NCCH partition.
+pub struct Ncch<'a> {
+    /// Title ID, also used as the partition and program ID.
+    pub title_id: u64,
+    /// Product code, such as `CTR-P-ABCD`.
+    pub product_code: &'a str,
+    /// Process name, truncated to 8 bytes.
+    pub name: &'a str,
+    /// ELF executable, linked at [`CODE_ADDRESS`].
+    pub elf: &'a [u8],
+    pub smdh: &'a [u8],
+    /// Banner in the `.bnr` format.
+    pub banner: Option<&'a [u8]>,
+    pub romfs: Option<&'a RomFs>,
+}
+
+/// The header of a partition, with the process name of its extended header.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Header {
+    pub title_id: u64,
+    pub product_code: String,
+    pub name: String,
+    pub exefs_size: u64,
+    /// Offset of the IVFC-wrapped `RomFS` from the start of the partition.
+    pub romfs_offset: u64,
+    /// Zero if there is no `RomFS`.
+    pub romfs_size: u64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not a valid ARM executable.
+    Elf(elf::ParseError),
+    /// The program segments are not laid out the way the loader expects.
+    Layout(String),
+    /// The `RomFS` contents could not be read.
+    Io(io::Error),
+}
+
+/// Load address, page count and size of a segment.
+#[derive(Default)]
+struct CodeSetInfo {
+    address: u32,
+    pages: u32,
+    size: u32,
+}
+
+impl Ncch<'_> {
+    /// Build the partition.
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let (code, code_sets, bss_size) = load_code(self.elf)?;
+
+        let mut exefs_files = vec![(".code", &code[..]), ("icon", self.smdh)];
+        if let Some(banner) = self.banner {
+            exefs_files.push(("banner", banner));
+        }
+        let exefs = build_exefs(&exefs_files);
+
+        let romfs = self
+            .romfs
+            .map(|romfs| {
+                let mut level3 = Vec::with_capacity(romfs.size() as usize);
+                romfs.write_to(&mut level3)?;
+                Ok(build_ivfc(&level3))
+            })
+            .transpose()
+            .map_err(Error::Io)?;
+
+        let exheader = self.build_exheader(&code_sets, bss_size);
+
+        let exefs_offset = HEADER_SIZE + exheader.len();
+        let romfs_offset = align_usize(exefs_offset + exefs.len(), IVFC_BLOCK_SIZE);
+        let size = match &romfs {
+            Some((romfs, _)) => romfs_offset + romfs.len(),
+            None => exefs_offset + exefs.len(),
+        };
+
+        let mut out = vec![0; align_usize(size, MEDIA_UNIT)];
+        out[exefs_offset..][..exefs.len()].copy_from_slice(&exefs);
+        out[HEADER_SIZE..][..exheader.len()].copy_from_slice(&exheader);
+
+        let header = &mut out[..HEADER_SIZE];
+        // The signature at 0x0 is left empty.
+        header[0x100..0x104].copy_from_slice(b"NCCH");
+        put_u32(header, 0x104, units(size));
+        put_u64(header, 0x108, self.title_id);
+        header[0x110..0x112].copy_from_slice(b"00"); // maker code
+        put_u16(header, 0x112, 2); // version
+        put_u64(header, 0x118, self.title_id);
+        put_str(&mut header[0x150..0x160], self.product_code);
+        header[0x160..0x180].copy_from_slice(&sha256(&exheader[..EXHEADER_SIZE]));
+        put_u32(header, 0x180, EXHEADER_SIZE as u32);
+
+        let flags = &mut header[0x188..0x190];
+        flags[4] = 1; // content platform: CTR
+        flags[5] = 0x3; // content type: executable with data
+        flags[7] = 0x4; // no encryption
+        if romfs.is_none() {
+            flags[7] |= 0x2; // no RomFS to mount
+        }
+
+        put_u32(header, 0x1A0, units(exefs_offset));
+        put_u32(header, 0x1A4, units(exefs.len()));
+        put_u32(header, 0x1A8, units(EXEFS_HEADER_SIZE));
+        header[0x1C0..0x1E0].copy_from_slice(&sha256(&exefs[..EXEFS_HEADER_SIZE]));
+
+        if let Some((romfs, hash_region_size)) = &romfs {
+            let header = &mut out[..HEADER_SIZE];
+            put_u32(header, 0x1B0, units(romfs_offset));
+            put_u32(header, 0x1B4, units(romfs.len()));
+            put_u32(header, 0x1B8, units(*hash_region_size));
+            header[0x1E0..0x200].copy_from_slice(&sha256(&romfs[..*hash_region_size]));
+            out[romfs_offset..][..romfs.len()].copy_from_slice(romfs);
+        }
+
+        Ok(out)
+    }
+
+    /// Build the extended header, followed by the access descriptor.
+    fn build_exheader(&self, code_sets: &[CodeSetInfo; 3], bss_size: u32) -> Vec<u8> {
+        let mut exheader = vec![0; EXHEADER_SIZE + ACCESS_DESC_SIZE];
+
+        // System control info
+        let sci = &mut exheader[..0x200];
+        put_str(&mut sci[..0x8], self.name);
+        sci[0xD] = 0x2; // SD application
+        for (info, offset) in code_sets.iter().zip([0x10, 0x20, 0x30]) {
+            put_u32(sci, offset, info.address);
+            put_u32(sci, offset + 0x4, info.pages);
+            put_u32(sci, offset + 0x8, info.size);
+        }
+        put_u32(sci, 0x1C, STACK_SIZE);
+        put_u32(sci, 0x3C, bss_size);
+        for (i, dependency) in DEPENDENCIES.iter().enumerate() {
+            put_u64(sci, 0x40 + i * 8, *dependency);
+        }
+        put_u64(sci, 0x1C8, self.title_id); // jump ID
+
+        // Access control info, also copied into the access descriptor
+        let aci = build_aci(self.title_id, self.romfs.is_some());
+        exheader[0x200..0x400].copy_from_slice(&aci);
+        // The signature and public key of the access descriptor are left empty.
+        exheader[0x600..0x800].copy_from_slice(&aci);
+
+        exheader
+    }
+}
+
+/// Build the access control info, granting what libctru applications need.
+fn build_aci(program_id: u64, has_romfs: bool) -> Vec<u8> {
+    let mut aci = vec![0; 0x200];
+
+    // ARM11 local system capabilities
+    put_u64(&mut aci, 0x0, program_id);
+    put_u32(&mut aci, 0x8, CORE_VERSION);
+    aci[0xE] = 0x1 << 2; // affinity mask: core 0
+    aci[0xF] = 0x30; // priority
+    put_u16(&mut aci, 0x10, 0x9E); // maximum CPU time
+    if !has_romfs {
+        aci[0x4F] = 0x1;
+    }
+    for (i, service) in SERVICES.iter().enumerate() {
+        put_str(&mut aci[0x50 + i * 8..][..8], service);
+    }
+
+    // ARM11 kernel capabilities
+    let mut descriptors = Vec::new();
+    for table in 0..=MAX_SVC / 24 {
+        let mask = (0..24)
+            .filter(|bit| (1..=MAX_SVC).contains(&(table * 24 + bit)))
+            .fold(0, |mask, bit| mask | 1 << bit);
+        descriptors.push(0xF000_0000 | table << 24 | mask);
+    }
+    descriptors.extend([
+        0xFC00_0000 | 2 << 8 | 33, // kernel version 2.33
+        0xFE00_0000 | 0x200,       // handle table size
+        // Allow debug, non-alphanumeric names, shared page writing,
+        // main thread arguments, shared device memory, special memory and
+        // the second core, in the application memory region.
+        0xFF00_0000 | 0x316D,
+    ]);
+    // Address ranges of the I/O registers, and the VRAM as read-only
+    for (start, end, read_only) in [
+        (0x1FF5_0000u32, 0x1FF5_8000u32, false),
+        (0x1FF7_0000, 0x1FF7_8000, false),
+        (0x1F00_0000, 0x1F60_0000, true),
+    ] {
+        descriptors.push(0xFF80_0000 | u32::from(read_only) << 20 | start >> 12);
+        descriptors.push(0xFF80_0000 | u32::from(read_only) << 20 | end >> 12);
+    }
+    descriptors.resize(28, u32::MAX);
+    for (i, descriptor) in descriptors.into_iter().enumerate() {
+        put_u32(&mut aci, 0x170 + i * 4, descriptor);
+    }
+
+    // ARM9 access control: direct SD card access
+    put_u16(&mut aci, 0x1F0, 0x3 << 9);
+    aci[0x1FF] = 2; // descriptor version
+
+    aci
+}
+
+/// Lay out the segments of the executable as a `.code` file, returning it
+/// with the code set info of each segment and the BSS size.
+fn load_code(data: &[u8]) -> Result<(Vec<u8>, [CodeSetInfo; 3], u32), Error> {
+    let elf = Elf::parse(data)?;
+    let segments = elf.program_segments()?;
+
+    let mut code = Vec::new();
+    let mut code_sets: [CodeSetInfo; 3] = Default::default();
+    let mut bss_size = 0;
+    let mut address = CODE_ADDRESS;
+
+    for (index, segment) in segments.iter().enumerate() {
+        code_sets[index].address = address;
+        let Some(segment) = segment else { continue };
+
+        if segment.vaddr != address {
+            return Err(Error::Layout(format!(
+                "{} segment is at {:#010x}, expected {address:#010x}",
+                ["code", "rodata", "data"][index],
+                segment.vaddr
+            )));
+        }
+
+        let size = if index == 2 {
+            bss_size = segment.mem_size.saturating_sub(segment.data.len() as u32);
+            segment.data.len() as u32
+        } else {
+            segment.mem_size
+        };
+        let pages = align(size, PAGE_SIZE) / PAGE_SIZE;
+        code_sets[index].pages = pages;
+        code_sets[index].size = size;
+
+        code.extend_from_slice(segment.data);
+        if index < 2 {
+            code.resize(
+                code.len() + (pages * PAGE_SIZE) as usize - segment.data.len(),
+                0,
+            );
+        }
+        address += pages * PAGE_SIZE;
+    }
+
+    if segments[0].is_none() {
+        return Err(Error::Layout(String::from("no code segment")));
+    }
+
+    Ok((code, code_sets, bss_size))
+}
+
+/// Build an ExeFS from named files.
+fn build_exefs(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut exefs = vec![0; EXEFS_HEADER_SIZE];
+
+    for (i, (name, contents)) in files.iter().enumerate() {
+        let offset = exefs.len() - EXEFS_HEADER_SIZE;
+        put_str(&mut exefs[i * 0x10..][..8], name);
+        put_u32(&mut exefs, i * 0x10 + 0x8, offset as u32);
+        put_u32(&mut exefs, i * 0x10 + 0xC, contents.len() as u32);
+        // Hashes are stored in reverse order, from the end of the header.
+        exefs[0xC0 + (9 - i) * 0x20..][..0x20].copy_from_slice(&sha256(contents));
+
+        exefs.extend_from_slice(contents);
+        exefs.resize(align_usize(exefs.len(), MEDIA_UNIT), 0);
+    }
+
+    exefs
+}
+
+/// Wrap a `RomFS` image in an IVFC hash tree, returning it with the size of
+/// the region covered by the superblock hash.
+fn build_ivfc(level3: &[u8]) -> (Vec<u8>, usize) {
+    let hash_blocks = |data: &[u8]| {
+        data.chunks(IVFC_BLOCK_SIZE)
+            .flat_map(|block| {
+                let mut padded = [0; IVFC_BLOCK_SIZE];
+                padded[..block.len()].copy_from_slice(block);
+                sha256(&padded)
+            })
+            .collect::<Vec<_>>()
+    };
+    let level2 = hash_blocks(level3);
+    let level1 = hash_blocks(&level2);
+    let master_hash = hash_blocks(&level1);
+
+    let hash_region_size = align_usize(0x60 + master_hash.len(), MEDIA_UNIT);
+    let level3_offset = align_usize(0x60 + master_hash.len(), IVFC_BLOCK_SIZE);
+    let level1_offset = align_usize(level3_offset + level3.len(), IVFC_BLOCK_SIZE);
+    let level2_offset = align_usize(level1_offset + level1.len(), IVFC_BLOCK_SIZE);
+
+    let mut out = vec![0; level2_offset + level2.len()];
+    out[..4].copy_from_slice(b"IVFC");
+    put_u32(&mut out, 0x4, 0x10000);
+    put_u32(&mut out, 0x8, master_hash.len() as u32);
+
+    // Logical offsets of the levels, as if they were laid out in order
+    let mut logical_offset = 0;
+    for (i, size) in [level1.len(), level2.len(), level3.len()]
+        .into_iter()
+        .enumerate()
+    {
+        let header = 0xC + i * 0x18;
+        put_u64(&mut out, header, logical_offset as u64);
+        put_u64(&mut out, header + 0x8, size as u64);
+        put_u32(&mut out, header + 0x10, IVFC_BLOCK_SIZE.trailing_zeros());
+        logical_offset = align_usize(logical_offset + size, IVFC_BLOCK_SIZE);
+    }
+    put_u32(&mut out, 0x58, IVFC_HEADER_SIZE as u32);
+
+    out[0x60..][..master_hash.len()].copy_from_slice(&master_hash);
+    out[level3_offset..][..level3.len()].copy_from_slice(level3);
+    out[level1_offset..][..level1.len()].copy_from_slice(&level1);
+    out[level2_offset..][..level2.len()].copy_from_slice(&level2);
+
+    (out, hash_region_size)
+}
+
+/// Unwrap the level 3 `RomFS` image from an IVFC hash tree, or `None` if the
+/// data is not one.
+pub fn ivfc_level3(ivfc: &[u8]) -> Option<&[u8]> {
+    if ivfc.get(..4)? != b"IVFC" {
+        return None;
+    }
+    let word = |offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(ivfc.get(offset..offset + 4)?.try_into().unwrap()) as usize)
+    };
+    let block_size = 1usize.checked_shl(word(0x4C)? as u32)?;
+    let offset = (0x60usize.checked_add(word(0x8)?)?).checked_next_multiple_of(block_size)?;
+    let size = u64::from_le_bytes(ivfc.get(0x44..0x4C)?.try_into().unwrap());
+    ivfc.get(offset..)?.get(..usize::try_from(size).ok()?)
+}
+
+impl Header {
+    /// Read the header of a partition, or `None` if the data is not one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(0x100..0x104)? != b"NCCH" || data.len() < 0x208 {
+            return None;
+        }
+        let size = |offset: usize| {
+            u64::from(u32::from_le_bytes(
+                data[offset..offset + 4].try_into().unwrap(),
+            )) * MEDIA_UNIT as u64
+        };
+
+        Some(Self {
+            title_id: u64::from_le_bytes(data[0x108..0x110].try_into().unwrap()),
+            product_code: read_str(&data[0x150..0x160]),
+            name: read_str(&data[0x200..0x208]),
+            exefs_size: size(0x1A4),
+            romfs_offset: size(0x1B0),
+            romfs_size: size(0x1B4),
+        })
+    }
+}
+
+pub(crate) fn sha256(data: &[u8]) -> [u8; 0x20] {
+    Sha256::digest(data).into()
+}
+
+fn units(size: usize) -> u32 {
+    (align_usize(size, MEDIA_UNIT) / MEDIA_UNIT) as u32
+}
+
+pub(crate) fn align_usize(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+fn put_u16(out: &mut [u8], offset: usize, value: u16) {
+    out[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u32(out: &mut [u8], offset: usize, value: u32) {
+    out[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub(crate) fn put_u64(out: &mut [u8], offset: usize, value: u64) {
+    out[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Write a string into a fixed-size field, truncating it if needed.
+pub(crate) fn put_str(out: &mut [u8], value: &str) {
+    let len = value.len().min(out.len());
+    out[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+/// Read a zero-padded string field.
+pub(crate) fn read_str(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+impl From<elf::ParseError> for Error {
+    fn from(err: elf::ParseError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Elf(err) => write!(f, "{err}"),
+            Self::Layout(msg) => write!(f, "unsupported segment layout: {msg}"),
+            Self::Io(err) => write!(f, "could not read RomFS: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::threedsx::tests::build_elf;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn build_partition() {
+        let elf = build_elf(&[0xAA; 0x1800], &[0xBB; 0x10], &[0xCC; 0x8], &[]);
+        let smdh = vec![0x11; 0x36C0];
+        let ncch = Ncch {
+            title_id: 0x0004_0000_0FF3_FF00,
+            product_code: "CTR-P-TEST",
+            name: "a long title",
+            elf: &elf,
+            smdh: &smdh,
+            banner: None,
+            romfs: None,
+        }
+        .build()
+        .unwrap();
+
+        assert_eq!(&ncch[0x100..0x104], b"NCCH");
+        assert_eq!(read_u32(&ncch, 0x104) as usize * MEDIA_UNIT, ncch.len());
+        assert_eq!(&ncch[0x150..0x160], b"CTR-P-TEST\0\0\0\0\0\0");
+        assert_eq!(ncch[0x18F], 0x6);
+        assert_eq!(&ncch[0x160..0x180], &sha256(&ncch[0x200..0x600]));
+
+        // Extended header
+        assert_eq!(&ncch[0x200..0x208], b"a long t");
+        let code_sets = [0x210, 0x220, 0x230]
+            .map(|offset| [0, 4, 8].map(|field| read_u32(&ncch, offset + field)));
+        assert_eq!(
+            code_sets,
+            [
+                [0x10_0000, 2, 0x1800],
+                [0x10_2000, 1, 0x10],
+                [0x10_3000, 1, 0x8]
+            ]
+        );
+        assert_eq!(read_u32(&ncch, 0x23C), 0x10); // BSS
+        assert_eq!(&ncch[0x400..0x600], &ncch[0x800..0xA00]);
+
+        // Access control info: no RomFS, then the service access list
+        let aci = &ncch[0x400..0x600];
+        assert_eq!(aci[0x4F], 0x1);
+        assert_eq!(&aci[0x50..0x58], b"APT:U\0\0\0");
+        assert_eq!(&aci[0x50 + 29 * 8..0x50 + 30 * 8], b"y2r:u\0\0\0");
+        assert!(aci[0x50 + 30 * 8..0x150].iter().all(|&b| b == 0));
+
+        // ExeFS, right after the extended header
+        assert_eq!(read_u32(&ncch, 0x1A0), 0xA00 / 0x200);
+        let exefs = &ncch[0xA00..];
+        assert_eq!(&exefs[..8], b".code\0\0\0");
+        assert_eq!(read_u32(exefs, 0xC), 0x3008);
+        assert_eq!(&exefs[0x10..0x18], b"icon\0\0\0\0");
+        assert_eq!(read_u32(exefs, 0x18), 0x3200);
+        assert_eq!(&exefs[0x200..0x204], &[0xAA; 4]);
+        assert_eq!(&exefs[0x2200..0x2204], &[0xBB; 4]);
+        assert_eq!(&exefs[0x3200..0x3204], &[0xCC; 4]);
+        assert_eq!(&exefs[0x1E0..0x200], &sha256(&exefs[0x200..0x3208]));
+        assert_eq!(&ncch[0x1C0..0x1E0], &sha256(&exefs[..0x200]));
+
+        assert_eq!(
+            Header::parse(&ncch),
+            Some(Header {
+                title_id: 0x0004_0000_0FF3_FF00,
+                product_code: String::from("CTR-P-TEST"),
+                name: String::from("a long t"),
+                exefs_size: 0x6C00,
+                romfs_offset: 0,
+                romfs_size: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn ivfc_levels() {
+        let level3 = vec![0x5A; 0x2100];
+        let (ivfc, hash_region_size) = build_ivfc(&level3);
+
+        assert_eq!(&ivfc[..4], b"IVFC");
+        assert_eq!(read_u32(&ivfc, 0x8), 0x20);
+        assert_eq!(hash_region_size, 0x200);
+
+        // Three blocks of level 3 are hashed into level 2, which fits in a
+        // single block, as does level 1.
+        assert_eq!(&ivfc[0x1000..0x3100], &level3[..]);
+        let level2 = &ivfc[0x5000..0x5060];
+        assert_eq!(&level2[..0x20], &sha256(&[0x5A; 0x1000]));
+        let level1 = &ivfc[0x4000..0x4020];
+        let mut block = [0; 0x1000];
+        block[..0x60].copy_from_slice(level2);
+        assert_eq!(level1, &sha256(&block));
+        block = [0; 0x1000];
+        block[..0x20].copy_from_slice(level1);
+        assert_eq!(&ivfc[0x60..0x80], &sha256(&block));
+
+        assert_eq!(ivfc_level3(&ivfc), Some(&level3[..]));
+        assert_eq!(ivfc_level3(&level3), None);
+    }
+}
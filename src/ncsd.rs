@@ -0,0 +1,175 @@
+//! Wrapping of an NCCH partition in an NCSD, the format of cartridge images
+//! (`.3ds` or `.cci` files).
+//!
+//! The image is not padded to the size of the card, and like the partition it
+//! holds, it is not signed. See <https://www.3dbrew.org/wiki/NCSD>.
+
+use crate::ncch::{put_u32, MEDIA_UNIT};
+
+use core::fmt;
+use serde::Deserialize;
+use std::io::{self, Write};
+
+/// Offset of the first partition, after the card info.
+const PARTITION_OFFSET: usize = 0x4000;
+
+/// Capacity of a game card.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MediaSize {
+    #[serde(rename = "128MB")]
+    Mb128,
+    #[serde(rename = "256MB")]
+    Mb256,
+    #[serde(rename = "512MB")]
+    Mb512,
+    #[serde(rename = "1GB")]
+    Gb1,
+    #[serde(rename = "2GB")]
+    Gb2,
+    #[serde(rename = "4GB")]
+    Gb4,
+}
+
+/// Kind of game card, which determines where save data is stored.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CardType {
+    /// Save data is stored in a separate flash chip.
+    #[default]
+    Card1,
+    /// Save data is stored in the writable area of the card itself.
+    Card2,
+}
+
+#[derive(Debug)]
+pub struct TooLarge {
+    pub size: u64,
+    pub media_size: MediaSize,
+}
+
+impl MediaSize {
+    const ALL: [Self; 6] = [
+        Self::Mb128,
+        Self::Mb256,
+        Self::Mb512,
+        Self::Gb1,
+        Self::Gb2,
+        Self::Gb4,
+    ];
+
+    pub fn bytes(self) -> u64 {
+        (128 << 20) << self as u32
+    }
+
+    /// The smallest card that can hold an image of the given size.
+    pub fn fitting(size: u64) -> Option<Self> {
+        Self::ALL.into_iter().find(|media| size <= media.bytes())
+    }
+}
+
+/// Write a cartridge image containing a single NCCH partition.
+///
+/// If no media size is given, the smallest one that fits is used.
+pub fn write_cci(
+    mut out: impl Write,
+    partition: &[u8],
+    media_size: Option<MediaSize>,
+    card_type: CardType,
+) -> io::Result<()> {
+    let image_size = (PARTITION_OFFSET + partition.len()) as u64;
+    let media_size = match media_size.or_else(|| MediaSize::fitting(image_size)) {
+        Some(media_size) if image_size <= media_size.bytes() => media_size,
+        media_size => {
+            let err = TooLarge {
+                size: image_size,
+                media_size: media_size.unwrap_or(MediaSize::Gb4),
+            };
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, err));
+        }
+    };
+
+    let mut header = vec![0; PARTITION_OFFSET];
+    // The signature at 0x0 is left empty.
+    header[0x100..0x104].copy_from_slice(b"NCSD");
+    put_u32(
+        &mut header,
+        0x104,
+        (media_size.bytes() / MEDIA_UNIT as u64) as u32,
+    );
+    header[0x108..0x110].copy_from_slice(&partition[0x108..0x110]); // media ID
+    put_u32(&mut header, 0x120, (PARTITION_OFFSET / MEDIA_UNIT) as u32);
+    put_u32(&mut header, 0x124, (partition.len() / MEDIA_UNIT) as u32);
+    header[0x160..0x180].copy_from_slice(&partition[0x160..0x180]); // exheader hash
+
+    let flags = &mut header[0x188..0x190];
+    flags[3] = match card_type {
+        CardType::Card1 => 1, // save data in NOR flash
+        CardType::Card2 => 2, // no separate save chip
+    };
+    flags[4] = 1; // media platform: CTR
+    flags[5] = match card_type {
+        CardType::Card1 => 1,
+        CardType::Card2 => 2,
+    };
+    header[0x190..0x198].copy_from_slice(&partition[0x108..0x110]); // partition ID
+
+    // Card info: the writable area of CARD2 starts after the partitions.
+    let writable_address = match card_type {
+        CardType::Card1 => u32::MAX,
+        CardType::Card2 => (image_size / MEDIA_UNIT as u64) as u32,
+    };
+    put_u32(&mut header, 0x200, writable_address);
+    // A copy of the partition header, without its signature
+    header[0x1100..0x1200].copy_from_slice(&partition[0x100..0x200]);
+
+    out.write_all(&header)?;
+    out.write_all(partition)
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "image of {} bytes does not fit in a {:?} card",
+            self.size, self.media_size
+        )
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn build_image() {
+        let mut partition = vec![0x42; 0x600];
+        partition[0x100..0x104].copy_from_slice(b"NCCH");
+        partition[0x108..0x110].copy_from_slice(&0x0004_0000_0FF3_FF00u64.to_le_bytes());
+
+        let mut cci = Vec::new();
+        write_cci(&mut cci, &partition, None, CardType::Card2).unwrap();
+
+        assert_eq!(&cci[0x100..0x104], b"NCSD");
+        assert_eq!(read_u32(&cci, 0x104), (128 << 20) / 0x200);
+        assert_eq!(&cci[0x108..0x110], &0x0004_0000_0FF3_FF00u64.to_le_bytes());
+        assert_eq!([read_u32(&cci, 0x120), read_u32(&cci, 0x124)], [0x20, 0x3]);
+        assert_eq!(cci[0x18D], 2);
+        assert_eq!(read_u32(&cci, 0x200), 0x23);
+        assert_eq!(&cci[0x1100..0x1104], b"NCCH");
+        assert_eq!(&cci[0x4000..], &partition[..]);
+    }
+
+    #[test]
+    fn media_sizes() {
+        assert_eq!(MediaSize::fitting(0x4600), Some(MediaSize::Mb128));
+        assert_eq!(MediaSize::fitting(300 << 20), Some(MediaSize::Mb512));
+        assert_eq!(MediaSize::fitting(5 << 30), None);
+        assert_eq!(MediaSize::Gb4.bytes(), 4 << 30);
+    }
+}
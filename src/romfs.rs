@@ -0,0 +1,580 @@
+//! Building and reading RomFS images, the read-only filesystem that can be embedded in a
+//! 3DSX or CIA and is mounted as `romfs:/` by libctru.
+//!
+//! This produces the level 3 image of the IVFC hash tree, which holds the
+//! directory and file metadata tables followed by the file data.
+//! See <https://www.3dbrew.org/wiki/RomFS>.
+
+use crate::ncch::sha256;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Size of the level 3 header.
+const HEADER_SIZE: u32 = 0x28;
+/// Alignment of the file data, and of each file within it.
+const DATA_ALIGNMENT: u64 = 0x10;
+/// Marks the absence of an entry in the metadata tables.
+const EMPTY: u32 = 0xFFFF_FFFF;
+
+/// A RomFS image built from a directory on the host.
+#[derive(Debug)]
+pub struct RomFs {
+    /// The header and metadata tables.
+    metadata: Vec<u8>,
+    /// Offset of the file data from the start of the image.
+    data_offset: u64,
+    /// The files to write in the data section, without duplicates, in order.
+    data: Vec<FileData>,
+    data_size: u64,
+}
+
+#[derive(Debug)]
+struct FileData {
+    path: PathBuf,
+    offset: u64,
+    size: u64,
+}
+
+/// A directory or file of an image, listed by [`read_entries`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Entry {
+    Dir {
+        /// The path from the root, e.g. `sub/dir`.
+        path: String,
+    },
+    File {
+        path: String,
+        /// Offset of the contents from the start of the image.
+        offset: u64,
+        size: u64,
+    },
+}
+
+struct Dir {
+    parent: usize,
+    name: Vec<u16>,
+    dirs: Vec<usize>,
+    files: Vec<usize>,
+}
+
+struct FileEntry {
+    parent: usize,
+    name: Vec<u16>,
+    path: PathBuf,
+    size: u64,
+}
+
+impl RomFs {
+    /// Build the metadata of an image containing everything in `root`.
+    /// File contents are only read again when writing the image.
+    pub fn from_dir(root: &Path) -> io::Result<Self> {
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        add_dir(root, 0, Vec::new(), &mut Vec::new(), &mut dirs, &mut files)?;
+
+        let mut dir_offsets = Vec::with_capacity(dirs.len());
+        let mut dir_table_size = 0;
+        for dir in &dirs {
+            dir_offsets.push(dir_table_size);
+            dir_table_size += 0x18 + name_size(&dir.name);
+        }
+
+        let mut file_offsets = Vec::with_capacity(files.len());
+        let mut file_table_size = 0;
+        for file in &files {
+            file_offsets.push(file_table_size);
+            file_table_size += 0x20 + name_size(&file.name);
+        }
+
+        let (data, data_offsets, data_size) = layout_data(&files)?;
+
+        let dir_bucket_count = hash_table_len(dirs.len());
+        let mut dir_buckets = vec![EMPTY; dir_bucket_count];
+        let mut dir_table = Vec::with_capacity(dir_table_size as usize);
+        for (i, dir) in dirs.iter().enumerate() {
+            let parent = dir_offsets[dir.parent];
+            let bucket = &mut dir_buckets[hash(parent, &dir.name) as usize % dir_bucket_count];
+
+            let sibling = dirs[dir.parent]
+                .dirs
+                .iter()
+                .skip_while(|&&sibling| sibling != i)
+                .nth(1)
+                .map_or(EMPTY, |&sibling| dir_offsets[sibling]);
+
+            for value in [
+                parent,
+                sibling,
+                dir.dirs.first().map_or(EMPTY, |&child| dir_offsets[child]),
+                dir.files
+                    .first()
+                    .map_or(EMPTY, |&child| file_offsets[child]),
+                *bucket,
+            ] {
+                dir_table.extend(value.to_le_bytes());
+            }
+            write_name(&dir.name, &mut dir_table);
+
+            *bucket = dir_offsets[i];
+        }
+
+        let file_bucket_count = hash_table_len(files.len());
+        let mut file_buckets = vec![EMPTY; file_bucket_count];
+        let mut file_table = Vec::with_capacity(file_table_size as usize);
+        for (i, file) in files.iter().enumerate() {
+            let parent = dir_offsets[file.parent];
+            let bucket = &mut file_buckets[hash(parent, &file.name) as usize % file_bucket_count];
+
+            let sibling = dirs[file.parent]
+                .files
+                .iter()
+                .skip_while(|&&sibling| sibling != i)
+                .nth(1)
+                .map_or(EMPTY, |&sibling| file_offsets[sibling]);
+
+            file_table.extend(parent.to_le_bytes());
+            file_table.extend(sibling.to_le_bytes());
+            file_table.extend(data_offsets[i].to_le_bytes());
+            file_table.extend(file.size.to_le_bytes());
+            file_table.extend(bucket.to_le_bytes());
+            write_name(&file.name, &mut file_table);
+
+            *bucket = file_offsets[i];
+        }
+
+        // Header, then the tables in order: directory hash table, directory
+        // metadata, file hash table, file metadata.
+        let mut metadata = Vec::new();
+        let tables = [
+            dir_buckets.iter().flat_map(|b| b.to_le_bytes()).collect(),
+            dir_table,
+            file_buckets.iter().flat_map(|b| b.to_le_bytes()).collect(),
+            file_table,
+        ];
+
+        metadata.extend(HEADER_SIZE.to_le_bytes());
+        let mut offset = HEADER_SIZE;
+        for table in &tables {
+            metadata.extend(offset.to_le_bytes());
+            metadata.extend((table.len() as u32).to_le_bytes());
+            offset += table.len() as u32;
+        }
+        let data_offset = align(u64::from(offset), DATA_ALIGNMENT);
+        metadata.extend((data_offset as u32).to_le_bytes());
+
+        for table in tables {
+            metadata.extend(table);
+        }
+
+        Ok(Self {
+            metadata,
+            data_offset,
+            data,
+            data_size,
+        })
+    }
+
+    /// Total size of the image in bytes.
+    pub fn size(&self) -> u64 {
+        self.data_offset + self.data_size
+    }
+
+    /// Write the image, reading the contents of each file.
+    pub fn write_to(&self, mut out: impl Write) -> io::Result<()> {
+        out.write_all(&self.metadata)?;
+
+        let mut position = self.metadata.len() as u64;
+        for file in &self.data {
+            let offset = self.data_offset + file.offset;
+            io::copy(&mut io::repeat(0).take(offset - position), &mut out)?;
+
+            let copied = io::copy(&mut File::open(&file.path)?, &mut out)?;
+            if copied != file.size {
+                return Err(io::Error::other(format!(
+                    "{} changed while building RomFS",
+                    file.path.display()
+                )));
+            }
+
+            position = offset + file.size;
+        }
+
+        Ok(())
+    }
+}
+
+/// List the directories and files of a level 3 image, depth-first with the
+/// files of a directory before its subdirectories. Returns `None` if the image
+/// is invalid, e.g. with names that are not safe to extract.
+pub fn read_entries(image: &[u8]) -> Option<Vec<Entry>> {
+    let word = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            image.get(offset..offset + 4)?.try_into().unwrap(),
+        ))
+    };
+    if word(0)? != HEADER_SIZE {
+        return None;
+    }
+    let table = |index: usize| -> Option<&[u8]> {
+        let offset = word(4 + index * 8)? as usize;
+        image.get(offset..offset.checked_add(word(8 + index * 8)? as usize)?)
+    };
+
+    let reader = Reader {
+        image,
+        dirs: table(1)?,
+        files: table(3)?,
+        data_offset: word(0x24)?.into(),
+    };
+    let mut entries = Vec::new();
+    reader.read_dir(0, "", &mut entries)?;
+    Some(entries)
+}
+
+/// Reads the metadata tables of an image.
+struct Reader<'a> {
+    image: &'a [u8],
+    dirs: &'a [u8],
+    files: &'a [u8],
+    data_offset: u64,
+}
+
+impl Reader<'_> {
+    fn read_dir(&self, offset: u32, path: &str, entries: &mut Vec<Entry>) -> Option<()> {
+        // Each entry can only be listed once, unless the links form a loop.
+        let max_entries = self.dirs.len() / 0x18 + self.files.len() / 0x20;
+
+        let [_, _, mut child, mut file] = read_words(self.dirs, offset, 0)?;
+        while file != EMPTY {
+            let [_, sibling, offset_low, offset_high, size_low, size_high] =
+                read_words(self.files, file, 0)?;
+            let offset = self.data_offset + (u64::from(offset_high) << 32 | u64::from(offset_low));
+            let size = u64::from(size_high) << 32 | u64::from(size_low);
+            self.image
+                .get(offset as usize..)?
+                .get(..usize::try_from(size).ok()?)?;
+
+            entries.push(Entry::File {
+                path: join(path, &read_name(self.files, file, 0x20)?),
+                offset,
+                size,
+            });
+            if entries.len() > max_entries {
+                return None;
+            }
+            file = sibling;
+        }
+
+        while child != EMPTY {
+            let [_, sibling] = read_words(self.dirs, child, 0)?;
+            let path = join(path, &read_name(self.dirs, child, 0x18)?);
+            entries.push(Entry::Dir { path: path.clone() });
+            if entries.len() > max_entries {
+                return None;
+            }
+            self.read_dir(child, &path, entries)?;
+            child = sibling;
+        }
+
+        Some(())
+    }
+}
+
+/// Read the first words of a metadata entry.
+fn read_words<const N: usize>(table: &[u8], entry: u32, offset: usize) -> Option<[u32; N]> {
+    let start = (entry as usize).checked_add(offset)?;
+    let words = table.get(start..start + N * 4)?;
+    Some(std::array::from_fn(|i| {
+        u32::from_le_bytes(words[i * 4..i * 4 + 4].try_into().unwrap())
+    }))
+}
+
+/// Read the name of a metadata entry, whose fixed fields take `fields_size`
+/// bytes. Names that could escape the extraction directory are rejected.
+fn read_name(table: &[u8], entry: u32, fields_size: usize) -> Option<String> {
+    let [len] = read_words(table, entry, fields_size - 4)?;
+    let start = entry as usize + fields_size;
+    let units: Vec<u16> = table
+        .get(start..start.checked_add(len as usize)?)?
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect();
+    let name = String::from_utf16(&units).ok()?;
+
+    let unsafe_name = name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']);
+    (!unsafe_name).then_some(name)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// Add the directory at `path` and everything in it, depth-first. The files of
+/// a directory come before its subdirectories, and both are sorted by name.
+/// `ancestors` holds the canonical paths of the directories above it, to
+/// detect symlinks to them.
+fn add_dir(
+    path: &Path,
+    parent: usize,
+    name: Vec<u16>,
+    ancestors: &mut Vec<PathBuf>,
+    dirs: &mut Vec<Dir>,
+    files: &mut Vec<FileEntry>,
+) -> io::Result<usize> {
+    let canonical = fs::canonicalize(path)?;
+    if ancestors.contains(&canonical) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} links to a directory containing it", path.display()),
+        ));
+    }
+    ancestors.push(canonical);
+
+    let index = dirs.len();
+    dirs.push(Dir {
+        parent,
+        name,
+        dirs: Vec::new(),
+        files: Vec::new(),
+    });
+
+    let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut subdirs = Vec::new();
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not valid UTF-8", path.display()),
+            )
+        })?;
+        let name = name.encode_utf16().collect();
+
+        // Follow symlinks to include whatever they point to.
+        let metadata = fs::metadata(&path)?;
+        if metadata.is_dir() {
+            subdirs.push((path, name));
+        } else if metadata.is_file() {
+            dirs[index].files.push(files.len());
+            files.push(FileEntry {
+                parent: index,
+                name,
+                path,
+                size: metadata.len(),
+            });
+        }
+    }
+
+    for (path, name) in subdirs {
+        let child = add_dir(&path, index, name, ancestors, dirs, files)?;
+        dirs[index].dirs.push(child);
+    }
+
+    ancestors.pop();
+    Ok(index)
+}
+
+/// Assign an offset in the data section to each file. Files with identical
+/// contents, i.e. the same size and SHA-256 hash, share their data.
+fn layout_data(files: &[FileEntry]) -> io::Result<(Vec<FileData>, Vec<u64>, u64)> {
+    let mut data: Vec<FileData> = Vec::new();
+    let mut offsets = Vec::with_capacity(files.len());
+    let mut by_hash: HashMap<(u64, [u8; 0x20]), u64> = HashMap::new();
+    let mut size = 0;
+
+    for file in files {
+        if file.size == 0 {
+            offsets.push(0);
+            continue;
+        }
+
+        let hash = sha256(&fs::read(&file.path)?);
+        let offset = *by_hash.entry((file.size, hash)).or_insert_with(|| {
+            let offset = align(size, DATA_ALIGNMENT);
+            data.push(FileData {
+                path: file.path.clone(),
+                offset,
+                size: file.size,
+            });
+            size = offset + file.size;
+            offset
+        });
+        offsets.push(offset);
+    }
+
+    Ok((data, offsets, size))
+}
+
+/// Size of a name in a metadata entry, padded to 4 bytes.
+fn name_size(name: &[u16]) -> u32 {
+    align(name.len() as u64 * 2, 4) as u32
+}
+
+/// Write the length of the name in bytes, then the padded name.
+fn write_name(name: &[u16], out: &mut Vec<u8>) {
+    out.extend((name.len() as u32 * 2).to_le_bytes());
+    out.extend(name.iter().flat_map(|c| c.to_le_bytes()));
+    out.resize(out.len() + (name_size(name) as usize - name.len() * 2), 0);
+}
+
+/// The hash used to find entries by parent and name.
+fn hash(parent: u32, name: &[u16]) -> u32 {
+    name.iter().fold(parent ^ 123_456_789, |hash, &c| {
+        hash.rotate_right(5) ^ u32::from(c)
+    })
+}
+
+/// The number of buckets in a hash table for the given number of entries,
+/// chosen to avoid small prime factors.
+fn hash_table_len(entries: usize) -> usize {
+    match entries {
+        0..=2 => 3,
+        3..=18 => entries | 1,
+        _ => (entries..)
+            .find(|n| [2, 3, 5, 7, 11, 13, 17].iter().all(|p| n % p != 0))
+            .unwrap(),
+    }
+}
+
+fn align(value: u64, alignment: u64) -> u64 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn build_image() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        fs::write(root.join("b.txt"), "hello").unwrap();
+        fs::write(root.join("sub/c.txt"), "world").unwrap();
+
+        let romfs = RomFs::from_dir(root).unwrap();
+        let mut image = Vec::new();
+        romfs.write_to(&mut image).unwrap();
+
+        // Two directories and three files, each table with three buckets
+        let header: Vec<u32> = (0..10).map(|i| u32_at(&image, i * 4)).collect();
+        assert_eq!(
+            header,
+            [0x28, 0x28, 0xC, 0x34, 0x38, 0x6C, 0xC, 0x78, 0x84, 0x100]
+        );
+        assert_eq!(romfs.size(), 0x115);
+        assert_eq!(image.len(), 0x115);
+
+        // Root directory: no sibling, first subdirectory after it, first file at 0
+        let root_dir = 0x34;
+        assert_eq!(u32_at(&image, root_dir), 0);
+        assert_eq!(u32_at(&image, root_dir + 4), EMPTY);
+        assert_eq!(u32_at(&image, root_dir + 8), 0x18);
+        assert_eq!(u32_at(&image, root_dir + 12), 0);
+        assert_eq!(u32_at(&image, root_dir + 20), 0);
+
+        // a.txt and b.txt share their data
+        let file_table = 0x78;
+        assert_eq!(u32_at(&image, file_table + 4), 0x2C);
+        assert_eq!(
+            &image[file_table + 8..file_table + 24],
+            &[0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            &image[file_table + 0x2C + 8..file_table + 0x2C + 16],
+            &[0; 8]
+        );
+        assert_eq!(
+            &image[file_table + 0x58..file_table + 0x5C],
+            &0x18u32.to_le_bytes()
+        );
+        assert_eq!(
+            &image[file_table + 0x58 + 8..file_table + 0x58 + 12],
+            &0x10u32.to_le_bytes()
+        );
+
+        assert_eq!(&image[0x100..0x105], b"hello");
+        assert_eq!(&image[0x110..], b"world");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycle() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("sub/loop")).unwrap();
+
+        let err = RomFs::from_dir(root).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn read_image() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("sub/empty")).unwrap();
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        fs::write(root.join("sub/c.txt"), "world").unwrap();
+
+        let mut image = Vec::new();
+        RomFs::from_dir(root).unwrap().write_to(&mut image).unwrap();
+
+        let entries = read_entries(&image).unwrap();
+        assert_eq!(
+            entries,
+            [
+                Entry::File {
+                    path: String::from("a.txt"),
+                    offset: 0x100,
+                    size: 5
+                },
+                Entry::Dir {
+                    path: String::from("sub")
+                },
+                Entry::File {
+                    path: String::from("sub/c.txt"),
+                    offset: 0x110,
+                    size: 5
+                },
+                Entry::Dir {
+                    path: String::from("sub/empty")
+                },
+            ]
+        );
+        assert_eq!(&image[0x110..0x115], b"world");
+
+        // A file named `..`
+        let mut evil = image.clone();
+        let name = u32_at(&image, 0x1C) as usize + 0x20;
+        evil[name..name + 4].copy_from_slice(b".\0.\0");
+        evil[name - 4..name].copy_from_slice(&4u32.to_le_bytes());
+        assert_eq!(read_entries(&evil), None);
+
+        assert_eq!(read_entries(&image[..0x40]), None);
+    }
+
+    #[test]
+    fn hash_table_sizes() {
+        assert_eq!(hash_table_len(0), 3);
+        assert_eq!(hash_table_len(4), 5);
+        assert_eq!(hash_table_len(7), 7);
+        assert_eq!(hash_table_len(19), 19);
+        assert_eq!(hash_table_len(20), 23);
+    }
+}
@@ -0,0 +1,156 @@
+//! Generation of new 3DS projects, ready to build with `cargo 3ds`.
+
+use crate::Error;
+
+use clap::ValueEnum;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+const CARGO_TOML: &str = include_str!("../templates/Cargo.toml.tmpl");
+const RUST_TOOLCHAIN: &str = include_str!("../templates/rust-toolchain.toml");
+const GITIGNORE: &str = include_str!("../templates/gitignore");
+const ROMFS_README: &str = include_str!("../templates/romfs.txt");
+
+const TEST_RUNNER_DEPENDENCY: &str = "
+[dev-dependencies]
+test-runner = { git = \"https://github.com/rust3ds/test-runner\" }
+";
+
+/// The kind of project to generate.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Template {
+    /// An executable printing to the console of the top screen.
+    #[default]
+    Console,
+    /// An executable drawing on the top screen with citro2d.
+    Citro2d,
+    /// A library whose tests run on the device with `cargo 3ds test`.
+    TestRunner,
+}
+
+impl Template {
+    /// The source files of the template, relative to the project directory.
+    fn files(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Console => &[("src/main.rs", include_str!("../templates/console/main.rs"))],
+            Self::Citro2d => &[
+                ("build.rs", include_str!("../templates/citro2d/build.rs")),
+                ("src/main.rs", include_str!("../templates/citro2d/main.rs")),
+            ],
+            Self::TestRunner => &[(
+                "src/lib.rs",
+                include_str!("../templates/test-runner/lib.rs"),
+            )],
+        }
+    }
+}
+
+/// Generate a project named `name` in `dir`, which is created if needed.
+/// Files that already exist in `dir` are kept, except for `Cargo.toml`: there
+/// must not be a package in the directory already.
+pub fn create(dir: &Path, name: &str, template: Template) -> Result<(), Error> {
+    check_name(name)?;
+
+    if dir.join("Cargo.toml").exists() {
+        return Err(Error::Config(format!(
+            "`cargo 3ds init` cannot be run on existing packages: {} already exists",
+            dir.join("Cargo.toml").display()
+        )));
+    }
+
+    let mut manifest = CARGO_TOML.replace("{{name}}", name);
+    if template == Template::TestRunner {
+        manifest.push_str(TEST_RUNNER_DEPENDENCY);
+    }
+
+    let mut files = vec![
+        ("Cargo.toml", manifest.as_str()),
+        ("rust-toolchain.toml", RUST_TOOLCHAIN),
+        (".gitignore", GITIGNORE),
+        ("romfs/README.txt", ROMFS_README),
+    ];
+    files.extend_from_slice(template.files());
+
+    for (path, contents) in files {
+        write_new(&dir.join(path), contents.as_bytes())?;
+    }
+
+    // Start from the default icon, so it's easy to find and replace.
+    if let Ok(devkitpro) = std::env::var("DEVKITPRO") {
+        let default_icon = Path::new(&devkitpro).join("libctru/default_icon.png");
+        if let Ok(icon) = fs::read(default_icon) {
+            write_new(&dir.join("icon.png"), &icon)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the name is a valid package name, like cargo does.
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid_chars = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    let starts_with_digit = name.starts_with(|c: char| c.is_ascii_digit());
+
+    if name.is_empty() || !valid_chars || starts_with_digit {
+        return Err(Error::Config(format!(
+            "invalid package name `{name}`: only ASCII letters, digits, `-` and `_` \
+            are allowed, and it must not start with a digit. Use `--name` to set another one"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Write a file, creating its parent directories, unless it already exists.
+fn write_new(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(parent).map_err(|e| Error::io("create", parent, e))?;
+
+    match File::options().write(true).create_new(true).open(path) {
+        Ok(mut file) => file
+            .write_all(contents)
+            .map_err(|e| Error::io("write", path, e)),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            eprintln!("Keeping existing {}", path.display());
+            Ok(())
+        }
+        Err(e) => Err(Error::io("create", path, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_project() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "// existing").unwrap();
+
+        create(dir, "hello-3ds", Template::TestRunner).unwrap();
+        let manifest = fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        let lib = fs::read_to_string(dir.join("src/lib.rs")).unwrap();
+        let again = create(dir, "hello-3ds", Template::TestRunner);
+        let romfs = dir.join("romfs/README.txt").exists();
+
+        assert!(manifest.contains("name = \"hello-3ds\""));
+        assert!(manifest.contains("[package.metadata.cargo-3ds]"));
+        assert!(manifest.contains("test-runner"));
+        assert_eq!(lib, "// existing");
+        assert!(romfs);
+        assert!(matches!(again, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn package_names() {
+        assert!(check_name("hello_3ds-2").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name("3ds").is_err());
+        assert!(check_name("hello world").is_err());
+    }
+}
@@ -0,0 +1,557 @@
+//! Encoding and decoding of SMDH files, which hold the title, publisher and icon shown for
+//! an application in the 3DS home menu.
+//!
+//! See <https://www.3dbrew.org/wiki/SMDH> for a description of the format.
+
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, ImageEncoder};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// Size in bytes of an encoded SMDH file.
+pub const SMDH_SIZE: usize = 0x36C0;
+
+/// Number of localized title slots in an SMDH.
+pub const TITLE_COUNT: usize = 16;
+
+/// Width and height of the small icon, used e.g. in the notification list.
+pub const SMALL_ICON_SIZE: u32 = 24;
+
+/// Width and height of the large icon shown in the home menu.
+pub const LARGE_ICON_SIZE: u32 = 48;
+
+/// The title is shown in the home menu.
+pub const FLAG_VISIBLE: u32 = 0x0001;
+/// The application is allowed to use the stereoscopic 3D effect.
+pub const FLAG_ALLOW_3D: u32 = 0x0004;
+/// Play time is recorded in the activity log.
+pub const FLAG_RECORD_USAGE: u32 = 0x0100;
+
+/// Region lockout value which allows the application to run in every region.
+pub const REGION_FREE: u32 = 0x7FFF_FFFF;
+
+const SHORT_DESCRIPTION_LEN: usize = 0x40;
+const LONG_DESCRIPTION_LEN: usize = 0x80;
+const PUBLISHER_LEN: usize = 0x40;
+
+/// The regions of the region lockout bits, from the lowest one.
+pub const REGIONS: [&str; 7] = [
+    "Japan",
+    "North America",
+    "Europe",
+    "Australia",
+    "China",
+    "Korea",
+    "Taiwan",
+];
+
+/// The names of the flags, from the lowest bit.
+pub const FLAG_NAMES: [&str; 13] = [
+    "visible",
+    "auto-boot",
+    "allow 3D",
+    "require EULA",
+    "autosave on exit",
+    "extended banner",
+    "region rating required",
+    "save data",
+    "record usage",
+    "",
+    "no SD save backups",
+    "",
+    "New 3DS exclusive",
+];
+
+/// The rating boards of the age rating slots, or `None` for reserved slots.
+pub const RATING_BOARDS: [Option<&str>; 16] = [
+    Some("CERO"),
+    Some("ESRB"),
+    None,
+    Some("USK"),
+    Some("PEGI"),
+    None,
+    Some("PEGI PRT"),
+    Some("BBFC"),
+    Some("COB"),
+    Some("GRB"),
+    Some("CGSRR"),
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+const SETTINGS_OFFSET: usize = 0x2008;
+const SMALL_ICON_OFFSET: usize = 0x2040;
+const LARGE_ICON_OFFSET: usize = 0x24C0;
+
+/// The strings stored in one of the localized title slots of an SMDH.
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Title {
+    /// The application name, limited to 64 UTF-16 code units.
+    pub short_description: String,
+    /// A longer description, limited to 128 UTF-16 code units.
+    pub long_description: String,
+    /// The author or publisher, limited to 64 UTF-16 code units.
+    pub publisher: String,
+}
+
+/// The languages of the title slots of an SMDH, named in metadata by their
+/// language code.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Language {
+    #[serde(rename = "ja")]
+    Japanese,
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "fr")]
+    French,
+    #[serde(rename = "de")]
+    German,
+    #[serde(rename = "it")]
+    Italian,
+    #[serde(rename = "es")]
+    Spanish,
+    #[serde(rename = "zh")]
+    SimplifiedChinese,
+    #[serde(rename = "ko")]
+    Korean,
+    #[serde(rename = "nl")]
+    Dutch,
+    #[serde(rename = "pt")]
+    Portuguese,
+    #[serde(rename = "ru")]
+    Russian,
+    #[serde(rename = "zh-TW")]
+    TraditionalChinese,
+}
+
+impl Language {
+    /// The languages in the order of their title slots.
+    pub const ALL: [Language; 12] = [
+        Self::Japanese,
+        Self::English,
+        Self::French,
+        Self::German,
+        Self::Italian,
+        Self::Spanish,
+        Self::SimplifiedChinese,
+        Self::Korean,
+        Self::Dutch,
+        Self::Portuguese,
+        Self::Russian,
+        Self::TraditionalChinese,
+    ];
+
+    /// The language code used in metadata, e.g. `fr`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::Japanese => "ja",
+            Self::English => "en",
+            Self::French => "fr",
+            Self::German => "de",
+            Self::Italian => "it",
+            Self::Spanish => "es",
+            Self::SimplifiedChinese => "zh",
+            Self::Korean => "ko",
+            Self::Dutch => "nl",
+            Self::Portuguese => "pt",
+            Self::Russian => "ru",
+            Self::TraditionalChinese => "zh-TW",
+        }
+    }
+}
+
+/// A square RGB icon image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Icon {
+    size: u32,
+    pixels: Vec<[u8; 3]>,
+}
+
+/// The contents of an SMDH file.
+#[derive(Clone, Debug)]
+pub struct Smdh {
+    pub titles: [Title; TITLE_COUNT],
+    /// One byte per rating board: whether the rating is set (0x80) or pending
+    /// (0x40), whether there is no age restriction (0x20), and the minimum age.
+    pub age_ratings: [u8; 0x10],
+    pub region_lockout: u32,
+    pub flags: u32,
+    pub small_icon: Icon,
+    pub large_icon: Icon,
+}
+
+impl Icon {
+    /// Create an icon from row-major RGBA pixels. Transparent pixels are
+    /// blended against black, since the SMDH icon format has no alpha channel.
+    pub fn from_rgba(size: u32, pixels: &[[u8; 4]]) -> Self {
+        assert_eq!(pixels.len(), (size * size) as usize, "icon must be square");
+
+        let pixels = pixels
+            .iter()
+            .map(|&[r, g, b, a]| {
+                let blend = |c: u8| (u16::from(c) * u16::from(a) / 255) as u8;
+                [blend(r), blend(g), blend(b)]
+            })
+            .collect();
+
+        Self { size, pixels }
+    }
+
+    /// Create an icon by resizing an image, which is stretched if it is not
+    /// square.
+    pub fn from_image(image: &DynamicImage, size: u32) -> Self {
+        let resized = image.resize_exact(size, size, FilterType::Lanczos3);
+        let pixels: Vec<[u8; 4]> = resized.to_rgba8().pixels().map(|pixel| pixel.0).collect();
+
+        Self::from_rgba(size, &pixels)
+    }
+
+    /// Create an icon of half the size, averaging each 2x2 block of pixels.
+    pub fn downscale(&self) -> Self {
+        let size = self.size / 2;
+        let mut pixels = Vec::with_capacity((size * size) as usize);
+
+        for y in 0..size {
+            for x in 0..size {
+                let mut sum = [0u16; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let pixel = self.pixel(x * 2 + dx, y * 2 + dy);
+                    for (total, channel) in sum.iter_mut().zip(pixel) {
+                        *total += u16::from(channel);
+                    }
+                }
+                pixels.push(sum.map(|total| (total / 4) as u8));
+            }
+        }
+
+        Self { size, pixels }
+    }
+
+    /// Width and height of the icon in pixels.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> [u8; 3] {
+        self.pixels[(y * self.size + x) as usize]
+    }
+
+    /// Encode the icon as a PNG image.
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(&self.pixels.concat(), self.size, self.size, ColorType::Rgb8)
+            .expect("encoding a PNG in memory should not fail");
+        png
+    }
+
+    /// The coordinates of the pixels in the order they are encoded: 8x8 tiles
+    /// with the pixels of each tile in Morton (Z-order) order.
+    fn tiled_coordinates(size: u32) -> impl Iterator<Item = (u32, u32)> {
+        (0..size).step_by(8).flat_map(move |tile_y| {
+            (0..size).step_by(8).flat_map(move |tile_x| {
+                (0..64).map(move |i| {
+                    let x = (i & 1) | ((i >> 1) & 2) | ((i >> 2) & 4);
+                    let y = ((i >> 1) & 1) | ((i >> 2) & 2) | ((i >> 3) & 4);
+                    (tile_x + x, tile_y + y)
+                })
+            })
+        })
+    }
+
+    /// Encode the icon as tiled RGB565.
+    fn encode(&self, out: &mut Vec<u8>) {
+        for (x, y) in Self::tiled_coordinates(self.size) {
+            let [r, g, b] = self.pixel(x, y);
+            let rgb565 = (u16::from(r) >> 3) << 11 | (u16::from(g) >> 2) << 5 | u16::from(b) >> 3;
+            out.extend(rgb565.to_le_bytes());
+        }
+    }
+
+    /// Decode an icon encoded by [`Icon::encode`], expanding each channel to
+    /// 8 bits.
+    fn decode(size: u32, data: &[u8]) -> Self {
+        let mut pixels = vec![[0; 3]; (size * size) as usize];
+        for ((x, y), rgb565) in Self::tiled_coordinates(size).zip(data.chunks_exact(2)) {
+            let rgb565 = u16::from_le_bytes([rgb565[0], rgb565[1]]);
+            let expand = |value: u16, bits: u32| (u32::from(value) * 255 / ((1 << bits) - 1)) as u8;
+            pixels[(y * size + x) as usize] = [
+                expand(rgb565 >> 11, 5),
+                expand(rgb565 >> 5 & 0x3F, 6),
+                expand(rgb565 & 0x1F, 5),
+            ];
+        }
+
+        Self { size, pixels }
+    }
+}
+
+impl Smdh {
+    /// Create an SMDH with the same title in every language slot. The small
+    /// icon is derived from the large one.
+    pub fn new(title: Title, large_icon: Icon) -> Self {
+        Self::with_icons(title, large_icon.downscale(), large_icon)
+    }
+
+    /// Create an SMDH with the same title in every language slot.
+    pub fn with_icons(title: Title, small_icon: Icon, large_icon: Icon) -> Self {
+        assert_eq!(small_icon.size, SMALL_ICON_SIZE);
+        assert_eq!(large_icon.size, LARGE_ICON_SIZE);
+
+        Self {
+            titles: std::array::from_fn(|_| title.clone()),
+            age_ratings: [0; 0x10],
+            region_lockout: REGION_FREE,
+            flags: FLAG_VISIBLE | FLAG_ALLOW_3D | FLAG_RECORD_USAGE,
+            small_icon,
+            large_icon,
+        }
+    }
+
+    /// Replace the title shown for a language.
+    pub fn set_title(&mut self, language: Language, title: Title) {
+        self.titles[language as usize] = title;
+    }
+
+    /// Encode the SMDH into its binary representation.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(SMDH_SIZE);
+
+        out.extend(b"SMDH");
+        out.extend(0u16.to_le_bytes()); // version
+        out.extend(0u16.to_le_bytes()); // reserved
+
+        for title in &self.titles {
+            encode_utf16(&title.short_description, SHORT_DESCRIPTION_LEN, &mut out);
+            encode_utf16(&title.long_description, LONG_DESCRIPTION_LEN, &mut out);
+            encode_utf16(&title.publisher, PUBLISHER_LEN, &mut out);
+        }
+
+        // Application settings. Matchmaker IDs are left unset.
+        out.extend(self.age_ratings);
+        out.extend(self.region_lockout.to_le_bytes());
+        out.extend([0; 0xC]); // matchmaker IDs
+        out.extend(self.flags.to_le_bytes());
+        out.extend(0u16.to_le_bytes()); // EULA version
+        out.extend(0u16.to_le_bytes()); // reserved
+        out.extend(0f32.to_le_bytes()); // optimal animation default frame
+        out.extend(0u32.to_le_bytes()); // StreetPass ID
+        out.extend([0; 8]); // reserved
+
+        self.small_icon.encode(&mut out);
+        self.large_icon.encode(&mut out);
+
+        debug_assert_eq!(out.len(), SMDH_SIZE);
+        out
+    }
+
+    /// Decode an SMDH, or `None` if the data is not one.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < SMDH_SIZE || &data[..4] != b"SMDH" {
+            return None;
+        }
+
+        let titles = std::array::from_fn(|slot| {
+            let base = 8 + slot * 0x200;
+            let short_end = base + SHORT_DESCRIPTION_LEN * 2;
+            let long_end = short_end + LONG_DESCRIPTION_LEN * 2;
+            Title {
+                short_description: decode_utf16(&data[base..short_end]),
+                long_description: decode_utf16(&data[short_end..long_end]),
+                publisher: decode_utf16(&data[long_end..long_end + PUBLISHER_LEN * 2]),
+            }
+        });
+        let word = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+
+        Some(Self {
+            titles,
+            age_ratings: data[SETTINGS_OFFSET..SETTINGS_OFFSET + 0x10]
+                .try_into()
+                .unwrap(),
+            region_lockout: word(SETTINGS_OFFSET + 0x10),
+            flags: word(SETTINGS_OFFSET + 0x20),
+            small_icon: Icon::decode(SMALL_ICON_SIZE, &data[SMALL_ICON_OFFSET..]),
+            large_icon: Icon::decode(LARGE_ICON_SIZE, &data[LARGE_ICON_OFFSET..]),
+        })
+    }
+
+    /// Write the encoded SMDH to `writer`.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+}
+
+/// Write `s` as UTF-16LE into a zero-padded field of `len` code units,
+/// truncating it if it does not fit.
+fn encode_utf16(s: &str, len: usize, out: &mut Vec<u8>) {
+    let mut units: Vec<u16> = s.encode_utf16().take(len).collect();
+    // Don't leave half of a surrogate pair at the end of a truncated string.
+    if units.len() == len && (0xD800..0xDC00).contains(&units[len - 1]) {
+        units.pop();
+    }
+    units.resize(len, 0);
+
+    for unit in units {
+        out.extend(unit.to_le_bytes());
+    }
+}
+
+/// Read a zero-padded UTF-16LE field.
+fn decode_utf16(field: &[u8]) -> String {
+    let units: Vec<u16> = field
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid_icon(color: [u8; 4]) -> Icon {
+        Icon::from_rgba(LARGE_ICON_SIZE, &[color; 48 * 48])
+    }
+
+    fn test_title() -> Title {
+        Title {
+            short_description: String::from("Hello"),
+            long_description: String::from("A test app"),
+            publisher: String::from("Ferris"),
+        }
+    }
+
+    #[test]
+    fn header_and_titles() {
+        let bytes = Smdh::new(test_title(), solid_icon([0; 4])).to_bytes();
+
+        assert_eq!(bytes.len(), SMDH_SIZE);
+        assert_eq!(&bytes[..8], b"SMDH\0\0\0\0");
+
+        for slot in 0..TITLE_COUNT {
+            let base = 8 + slot * 0x200;
+            assert_eq!(&bytes[base..base + 10], b"H\0e\0l\0l\0o\0");
+            assert_eq!(&bytes[base + 10..base + 0x80], &[0; 0x76][..]);
+            assert_eq!(&bytes[base + 0x80..base + 0x82], b"A\0");
+            assert_eq!(&bytes[base + 0x180..base + 0x184], b"F\0e\0");
+        }
+
+        assert_eq!(bytes[0x2018..0x201C], REGION_FREE.to_le_bytes());
+        assert_eq!(bytes[0x2028..0x202C], 0x0105u32.to_le_bytes());
+    }
+
+    #[test]
+    fn localized_title() {
+        let mut smdh = Smdh::new(test_title(), solid_icon([0; 4]));
+        smdh.set_title(
+            Language::French,
+            Title {
+                short_description: String::from("Bonjour"),
+                ..test_title()
+            },
+        );
+        let bytes = smdh.to_bytes();
+
+        let slot = |language: Language| 8 + language as usize * 0x200;
+        assert_eq!(&bytes[slot(Language::French)..][..4], b"B\0o\0");
+        assert_eq!(&bytes[slot(Language::German)..][..4], b"H\0e\0");
+        assert_eq!(slot(Language::TraditionalChinese), 8 + 11 * 0x200);
+    }
+
+    #[test]
+    fn long_titles_are_truncated() {
+        let title = Title {
+            short_description: "🦀".repeat(40),
+            ..test_title()
+        };
+        let bytes = Smdh::new(title, solid_icon([0; 4])).to_bytes();
+
+        // 32 crabs fit exactly, and the field is not zero-terminated
+        assert_eq!(
+            &bytes[8 + 0x7C..8 + 0x80],
+            "🦀"
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(&bytes[8 + 0x80..8 + 0x82], b"A\0");
+
+        let mut out = Vec::new();
+        encode_utf16("a🦀", 2, &mut out);
+        assert_eq!(out, b"a\0\0\0");
+    }
+
+    #[test]
+    fn decode() {
+        let mut pixels = [[0, 0, 0, 255]; 48 * 48];
+        pixels[48 + 1] = [255, 0, 0, 255];
+        pixels[8 * 48 + 9] = [0, 255, 255, 255];
+        let mut smdh = Smdh::new(test_title(), Icon::from_rgba(48, &pixels));
+        smdh.set_title(
+            Language::TraditionalChinese,
+            Title {
+                short_description: "🦀".repeat(40),
+                ..test_title()
+            },
+        );
+        smdh.age_ratings[1] = 0x80 | 10;
+
+        let decoded = Smdh::from_bytes(&smdh.to_bytes()).unwrap();
+        assert_eq!(decoded.titles[0], test_title());
+        assert_eq!(decoded.titles[11].short_description, "🦀".repeat(32));
+        assert_eq!(decoded.age_ratings, smdh.age_ratings);
+        assert_eq!(decoded.flags, smdh.flags);
+        assert_eq!(decoded.region_lockout, REGION_FREE);
+        assert_eq!(decoded.large_icon, smdh.large_icon);
+        assert_eq!(decoded.small_icon.pixel(0, 0), [57, 0, 0]);
+
+        assert!(Smdh::from_bytes(b"SMDH").is_none());
+    }
+
+    #[test]
+    fn icon_from_image() {
+        // A wide image is stretched to fill the icon.
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_fn(192, 96, |x, _| {
+            if x < 96 {
+                image::Rgb([255, 0, 0])
+            } else {
+                image::Rgb([0, 0, 255])
+            }
+        }));
+
+        let large = Icon::from_image(&image, LARGE_ICON_SIZE);
+        assert_eq!(large.size(), 48);
+        assert_eq!(large.pixel(0, 47), [255, 0, 0]);
+        assert_eq!(large.pixel(47, 0), [0, 0, 255]);
+
+        let small = Icon::from_image(&image, SMALL_ICON_SIZE);
+        assert_eq!(small.size(), 24);
+        assert_eq!(small.pixel(23, 23), [0, 0, 255]);
+    }
+
+    #[test]
+    fn icon_tiling() {
+        let mut pixels = [[0, 0, 0, 255]; 48 * 48];
+        pixels[48 + 1] = [255, 0, 0, 255]; // (1, 1): tile 0, index 3
+        pixels[2] = [0, 255, 0, 255]; // (2, 0): tile 0, index 4
+        pixels[8] = [0, 0, 255, 255]; // (8, 0): tile 1, index 0
+        pixels[8 * 48] = [255, 255, 255, 128]; // (0, 8): tile 6, index 0
+
+        let bytes = Smdh::new(test_title(), Icon::from_rgba(48, &pixels)).to_bytes();
+        let large = &bytes[LARGE_ICON_OFFSET..];
+
+        assert_eq!(large[6..8], 0xF800u16.to_le_bytes());
+        assert_eq!(large[8..10], 0x07E0u16.to_le_bytes());
+        assert_eq!(large[128..130], 0x001Fu16.to_le_bytes());
+        assert_eq!(large[6 * 128..6 * 128 + 2], 0x8410u16.to_le_bytes());
+
+        // The small icon averages each 2x2 block: (0, 0) has one red pixel
+        let small = &bytes[SMALL_ICON_OFFSET..LARGE_ICON_OFFSET];
+        assert_eq!(small[0..2], 0x3800u16.to_le_bytes());
+    }
+}
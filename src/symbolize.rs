@@ -0,0 +1,256 @@
+//! Resolution of code addresses (e.g. from panics or crash dumps) to the
+//! functions and source lines of an ELF executable, from its debug info.
+
+use addr2line::gimli::{EndianRcSlice, RunTimeEndian};
+use addr2line::object::{self, Object, ObjectSection, ObjectSymbol};
+use addr2line::{gimli, Context};
+
+use core::fmt;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// A function containing an address. When the address is in a function
+/// inlined into another, there is a frame for each of them, innermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The demangled name of the function.
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+pub struct Symbolizer {
+    context: Context<EndianRcSlice<RunTimeEndian>>,
+    /// Function symbols sorted by address, for code without debug info.
+    symbols: Vec<(u64, String)>,
+    /// The address ranges of the executable sections.
+    code: Vec<Range<u64>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not a valid executable.
+    Object(object::Error),
+    /// The debug info of the executable is invalid.
+    Dwarf(gimli::Error),
+}
+
+impl Symbolizer {
+    /// Load the debug info and symbols of an executable.
+    pub fn new(elf: &[u8]) -> Result<Self, Error> {
+        let file = object::File::parse(elf).map_err(Error::Object)?;
+        let context = Context::new(&file).map_err(Error::Dwarf)?;
+
+        let mut symbols: Vec<_> = file
+            .symbols()
+            .filter(|symbol| symbol.kind() == object::SymbolKind::Text)
+            .filter_map(|symbol| Some((symbol.address(), symbol.name().ok()?.to_string())))
+            .collect();
+        symbols.sort();
+
+        let code = file
+            .sections()
+            .filter(|section| section.kind() == object::SectionKind::Text)
+            .map(|section| section.address()..section.address() + section.size())
+            .collect();
+
+        Ok(Self {
+            context,
+            symbols,
+            code,
+        })
+    }
+
+    /// The frames of the functions containing the address. If there is no
+    /// debug info for it, the function is taken from the symbol table, without
+    /// a location.
+    pub fn frames(&self, address: u64) -> Result<Vec<Frame>, Error> {
+        let mut frames = Vec::new();
+        let mut iter = self
+            .context
+            .find_frames(address)
+            .skip_all_loads()
+            .map_err(Error::Dwarf)?;
+
+        while let Some(frame) = iter.next().map_err(Error::Dwarf)? {
+            let function = match &frame.function {
+                Some(function) => Some(function.demangle().map_err(Error::Dwarf)?.into_owned()),
+                None => None,
+            };
+            let location = frame.location.as_ref();
+            frames.push(Frame {
+                function,
+                file: location.and_then(|l| l.file).map(str::to_string),
+                line: location.and_then(|l| l.line),
+                column: location.and_then(|l| l.column),
+            });
+        }
+
+        if frames.iter().all(|frame| frame.function.is_none()) {
+            if let Some(function) = self.symbol(address) {
+                match frames.first_mut() {
+                    Some(frame) => frame.function = Some(function),
+                    None => frames.push(Frame {
+                        function: Some(function),
+                        file: None,
+                        line: None,
+                        column: None,
+                    }),
+                }
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Whether the address is in the code of the executable, e.g. to tell
+    /// return addresses apart from data.
+    pub fn is_code(&self, address: u64) -> bool {
+        self.code.iter().any(|range| range.contains(&address))
+    }
+
+    /// The demangled name of the last function symbol at or before the address.
+    fn symbol(&self, address: u64) -> Option<String> {
+        let index = self
+            .symbols
+            .partition_point(|(symbol_address, _)| *symbol_address <= address);
+        let (_, name) = self.symbols.get(index.checked_sub(1)?)?;
+        Some(addr2line::demangle_auto(Cow::from(name.as_str()), None).into_owned())
+    }
+}
+
+/// Parse an address in hexadecimal, with or without a `0x` prefix.
+pub fn parse_address(address: &str) -> Option<u64> {
+    let digits = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Find the addresses written as `0x` followed by hexadecimal digits in a line
+/// of output, e.g. `pc: 0x00123abc`.
+pub fn find_addresses(line: &str) -> Vec<u64> {
+    line.match_indices("0x")
+        .filter_map(|(start, _)| {
+            let digits = &line[start + 2..];
+            let end = digits
+                .find(|c: char| !c.is_ascii_hexdigit())
+                .unwrap_or(digits.len());
+            u64::from_str_radix(&digits[..end], 16).ok()
+        })
+        .collect()
+}
+
+/// Write the frames of an address like `addr2line -i -p`: the innermost function,
+/// then each function it is inlined into.
+pub fn write_frames(
+    out: &mut dyn Write,
+    address: u64,
+    frames: &[Frame],
+    indent: &str,
+) -> io::Result<()> {
+    match frames.split_first() {
+        None => writeln!(out, "{indent}{address:#010x}: ??"),
+        Some((first, inlined_into)) => {
+            writeln!(out, "{indent}{address:#010x}: {first}")?;
+            for frame in inlined_into {
+                writeln!(out, "{indent} (inlined by) {frame}")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.function.as_deref().unwrap_or("??"))?;
+        if let Some(file) = &self.file {
+            write!(f, " at {file}")?;
+            if let Some(line) = self.line {
+                write!(f, ":{line}")?;
+                if let Some(column) = self.column {
+                    write!(f, ":{column}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Object(err) => write!(f, "invalid executable: {err}"),
+            Self::Dwarf(err) => write!(f, "invalid debug info: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_address("0x00123abc"), Some(0x123ABC));
+        assert_eq!(parse_address("123ABC"), Some(0x123ABC));
+        assert_eq!(parse_address("main"), None);
+
+        assert_eq!(
+            find_addresses("pc: 0x00123abc, lr: 0x00100004; sp 0x, 0xg"),
+            [0x123ABC, 0x100004]
+        );
+    }
+
+    #[test]
+    fn frame_display() {
+        let frame = Frame {
+            function: Some(String::from("app::main")),
+            file: Some(String::from("src/main.rs")),
+            line: Some(10),
+            column: Some(5),
+        };
+        assert_eq!(frame.to_string(), "app::main at src/main.rs:10:5");
+
+        let unknown = Frame {
+            function: None,
+            file: None,
+            line: None,
+            column: None,
+        };
+        assert_eq!(unknown.to_string(), "??");
+    }
+
+    // The test executable itself has the debug info to look up.
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn symbolize_self() {
+        let elf = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let symbolizer = Symbolizer::new(&elf).unwrap();
+
+        let address = symbolizer
+            .symbols
+            .iter()
+            .map(|(address, _)| *address)
+            .find(|&address| {
+                symbolizer.symbol(address).as_deref()
+                    == Some("cargo_3ds::symbolize::find_addresses")
+            })
+            .unwrap();
+        let frames = symbolizer.frames(address).unwrap();
+
+        let frame = frames.last().unwrap();
+        assert_eq!(
+            frame.function.as_deref(),
+            Some("cargo_3ds::symbolize::find_addresses")
+        );
+        assert!(frame.file.as_deref().unwrap().ends_with("symbolize.rs"));
+        assert!(symbolizer.is_code(address));
+        assert!(!symbolizer.is_code(0));
+    }
+}
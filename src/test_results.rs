@@ -0,0 +1,160 @@
+//! Collection of the results of test executables, from the summary line
+//! printed by the libtest harness when the tests finish.
+
+use core::fmt;
+use core::ops::AddAssign;
+use std::io::{self, BufRead, Write};
+
+/// The counts reported by a `test result:` line.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TestResult {
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub measured: usize,
+    pub filtered_out: usize,
+}
+
+impl TestResult {
+    /// Parse a line such as
+    /// `test result: ok. 3 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.01s`.
+    pub fn parse(line: &str) -> Option<Self> {
+        let counts = line
+            .trim()
+            .strip_prefix("test result: ")?
+            .split_once(". ")?
+            .1;
+
+        let mut result = Self::default();
+        for count in counts.split("; ") {
+            let (number, kind) = count.split_once(' ')?;
+            let field = match kind {
+                "passed" => &mut result.passed,
+                "failed" => &mut result.failed,
+                "ignored" => &mut result.ignored,
+                "measured" => &mut result.measured,
+                "filtered out" => &mut result.filtered_out,
+                // e.g. "finished in 0.01s"
+                _ => continue,
+            };
+            *field = number.parse().ok()?;
+        }
+
+        Some(result)
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Copy the output of a test executable to `out`, returning the sum of the
+/// results it reported, or `None` if it did not report any (e.g. because it
+/// crashed). The results are returned along with the error that stopped the
+/// copy, if any, since the executable may keep the connection open or lose it
+/// after reporting them.
+pub fn capture(output: impl BufRead, out: impl Write) -> (Option<TestResult>, io::Result<()>) {
+    let mut total = None;
+    let copied = copy_output(output, out, &mut total);
+    (total, copied)
+}
+
+fn copy_output(
+    output: impl BufRead,
+    mut out: impl Write,
+    total: &mut Option<TestResult>,
+) -> io::Result<()> {
+    for line in output.split(b'\n') {
+        let mut line = line?;
+        line.push(b'\n');
+        out.write_all(&line)?;
+
+        if let Some(result) = TestResult::parse(&String::from_utf8_lossy(&line)) {
+            *total.get_or_insert_with(TestResult::default) += result;
+        }
+    }
+    out.flush()
+}
+
+impl AddAssign for TestResult {
+    fn add_assign(&mut self, other: Self) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+        self.ignored += other.ignored;
+        self.measured += other.measured;
+        self.filtered_out += other.filtered_out;
+    }
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} passed; {} failed; {} ignored; {} measured; {} filtered out",
+            self.passed, self.failed, self.ignored, self.measured, self.filtered_out
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_result() {
+        assert_eq!(
+            TestResult::parse(
+                "test result: FAILED. 3 passed; 1 failed; 2 ignored; 0 measured; 4 filtered out; finished in 0.52s\n"
+            ),
+            Some(TestResult {
+                passed: 3,
+                failed: 1,
+                ignored: 2,
+                measured: 0,
+                filtered_out: 4,
+            })
+        );
+        assert_eq!(TestResult::parse("test foo ... ok"), None);
+        assert_eq!(TestResult::parse("test result: ok. many passed"), None);
+    }
+
+    #[test]
+    fn capture_output() {
+        let output = b"running 2 tests\ntest a ... ok\ntest b ... ok\n\n\
+            test result: ok. 2 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out\n\
+            test result: ok. 1 passed; 0 failed; 1 ignored; 0 measured; 0 filtered out";
+        let mut out = Vec::new();
+
+        let (result, copied) = capture(&output[..], &mut out);
+        copied.unwrap();
+        let result = result.unwrap();
+        assert_eq!((result.passed, result.ignored), (3, 1));
+        assert!(result.is_ok());
+        assert_eq!(out.len(), output.len() + 1);
+
+        let (result, copied) = capture(&b"panicked"[..], io::sink());
+        assert_eq!(result, None);
+        copied.unwrap();
+    }
+
+    #[test]
+    fn capture_until_error() {
+        // The executable reports its results, then stays silent until the
+        // read times out.
+        let results: &[u8] =
+            b"test result: ok. 2 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out\n";
+        let output = io::Read::chain(results, TimedOut);
+
+        let (result, copied) = capture(io::BufReader::new(output), io::sink());
+        assert_eq!(result.map(|result| result.passed), Some(2));
+        assert_eq!(copied.unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    struct TimedOut;
+
+    impl io::Read for TimedOut {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::TimedOut.into())
+        }
+    }
+}
@@ -0,0 +1,493 @@
+//! An implementation of the 3dslink protocol, used to send a 3DSX to the
+//! netloader of the Homebrew Launcher and run it.
+
+use flate2::{Compress, Compression, FlushCompress, Status};
+
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The port the netloader listens on, and that 3dslink listens on for
+/// discovery responses and redirected stdio.
+pub const NETLOADER_PORT: u16 = 17491;
+
+/// The number of discovery broadcasts to send by default.
+pub const DEFAULT_RETRIES: usize = 10;
+
+const DISCOVERY_REQUEST: &[u8] = b"3dsboot";
+const DISCOVERY_RESPONSE: &[u8] = b"boot3ds";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often to check for a connection before the deadline of
+/// [`Server::accept_timeout`].
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The file is compressed and sent in chunks of this size.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Find a 3DS running the netloader on the local network, by broadcasting
+/// a discovery request up to `retries + 1` times.
+pub fn discover(retries: usize) -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, NETLOADER_PORT))?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+
+    let mut buf = [0; 256];
+    for _ in 0..=retries {
+        socket.send_to(DISCOVERY_REQUEST, (Ipv4Addr::BROADCAST, NETLOADER_PORT))?;
+
+        loop {
+            match socket.recv_from(&mut buf) {
+                Ok((len, SocketAddr::V4(addr))) if buf[..len].starts_with(DISCOVERY_RESPONSE) => {
+                    return Ok(*addr.ip());
+                }
+                Ok(_) => continue,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no 3DS found on the network, make sure the netloader is running or pass --address",
+    ))
+}
+
+/// Send the 3DSX at `path` to the netloader at `addr`, and start it with
+/// the given `argv`.
+pub fn send(addr: SocketAddr, path: &Path, argv: &[String]) -> io::Result<()> {
+    let data = std::fs::read(path)?;
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid 3dsx file name"))?;
+
+    let mut stream = TcpStream::connect(addr)?;
+    eprintln!("Sending {name}, {} bytes to {}", data.len(), addr.ip());
+
+    write_len(&mut stream, name.len())?;
+    stream.write_all(name.as_bytes())?;
+    write_len(&mut stream, data.len())?;
+
+    match read_response(&mut stream)? {
+        0 => {}
+        -1 => return Err(io::Error::other("the device failed to create the file")),
+        -2 => return Err(io::Error::other("insufficient space on the device")),
+        -3 => return Err(io::Error::other("insufficient memory on the device")),
+        code => {
+            return Err(io::Error::other(format!(
+                "the device refused the file ({code})"
+            )))
+        }
+    }
+
+    let sent = send_compressed(&mut stream, &data)?;
+    eprintln!(
+        "{sent} bytes sent ({:.2}%)",
+        100.0 * sent as f64 / data.len().max(1) as f64
+    );
+
+    if read_response(&mut stream)? != 0 {
+        return Err(io::Error::other("the device failed to receive the file"));
+    }
+
+    // The arguments are sent as a single buffer of NUL-terminated strings.
+    let mut args = Vec::new();
+    for arg in argv {
+        args.extend(arg.as_bytes());
+        args.push(0);
+    }
+    write_len(&mut stream, args.len())?;
+    stream.write_all(&args)?;
+
+    Ok(())
+}
+
+/// The server that executables connect back to when redirecting their stdio
+/// (e.g. with libctru's `link3dsStdio`). It should be bound before sending the
+/// executable, so the connection can't be missed.
+pub struct Server {
+    listener: TcpListener,
+    device: Cell<Option<IpAddr>>,
+    sessions: Cell<usize>,
+}
+
+/// A connection from the executable. Reading from it yields the output of the
+/// executable until it disconnects.
+pub struct Session {
+    stream: TcpStream,
+    peer: SocketAddr,
+    number: usize,
+}
+
+impl Server {
+    /// Listen on the given port on all interfaces.
+    pub fn bind(port: u16) -> io::Result<Self> {
+        Self::from_listener(TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?)
+    }
+
+    /// Use an already bound listener.
+    pub fn from_listener(listener: TcpListener) -> io::Result<Self> {
+        Ok(Self {
+            listener,
+            device: Cell::new(None),
+            sessions: Cell::new(0),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Only accept connections from this address. Otherwise, the address of
+    /// the first connection is used.
+    pub fn set_device(&self, device: IpAddr) {
+        self.device.set(Some(device));
+    }
+
+    /// Wait for the device to connect, ignoring connections from other hosts.
+    pub fn accept(&self) -> io::Result<Session> {
+        self.accept_until(None)
+    }
+
+    /// Like [`Server::accept`], but fail with [`io::ErrorKind::TimedOut`] if the
+    /// device has not connected after `timeout`.
+    pub fn accept_timeout(&self, timeout: Duration) -> io::Result<Session> {
+        self.listener.set_nonblocking(true)?;
+        let session = self.accept_until(Some(Instant::now() + timeout));
+        self.listener.set_nonblocking(false)?;
+        session
+    }
+
+    /// Accept a connection from the device, polling until `deadline` if the
+    /// listener is non-blocking.
+    fn accept_until(&self, deadline: Option<Instant>) -> io::Result<Session> {
+        loop {
+            let (stream, peer) = match self.listener.accept() {
+                Ok(connection) => connection,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => match deadline {
+                    Some(deadline) if Instant::now() < deadline => {
+                        thread::sleep(ACCEPT_POLL_INTERVAL);
+                        continue;
+                    }
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "the device did not connect in time",
+                        ))
+                    }
+                },
+                Err(e) => return Err(e),
+            };
+            // Accepted streams may inherit the mode of the listener.
+            stream.set_nonblocking(false)?;
+
+            match self.device.get() {
+                Some(device) if device != peer.ip() => {
+                    eprintln!("[3dslink] Ignoring connection from {peer}, expected {device}");
+                    continue;
+                }
+                Some(_) => {}
+                None => self.device.set(Some(peer.ip())),
+            }
+
+            let number = self.sessions.get() + 1;
+            self.sessions.set(number);
+            eprintln!("[3dslink] Session {number}: connected to {peer}");
+
+            return Ok(Session {
+                stream,
+                peer,
+                number,
+            });
+        }
+    }
+
+    /// Copy the output of each session to `out`, until interrupted. When the
+    /// executable disconnects, wait for it (or the next one) to reconnect.
+    pub fn serve(&self, mut out: impl Write) -> io::Result<()> {
+        eprintln!(
+            "[3dslink] Waiting for the device on {} (press Ctrl-C to stop)",
+            self.local_addr()?
+        );
+
+        loop {
+            let mut session = self.accept()?;
+            let copied = io::copy(&mut session, &mut out);
+            out.flush()?;
+
+            match copied {
+                Ok(bytes) => eprintln!(
+                    "[3dslink] Session {}: {} disconnected after {bytes} bytes, waiting for reconnection",
+                    session.number,
+                    session.peer.ip()
+                ),
+                Err(e) => eprintln!(
+                    "[3dslink] Session {}: connection to {} lost: {e}, waiting for reconnection",
+                    session.number,
+                    session.peer.ip()
+                ),
+            }
+        }
+    }
+}
+
+impl Session {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// The number of this session, starting from 1 for the first connection
+    /// to the server.
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Fail reads with [`io::ErrorKind::WouldBlock`] or
+    /// [`io::ErrorKind::TimedOut`] when the executable is silent for `timeout`.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    pub fn into_stream(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl Read for Session {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+/// Compress `data` as a zlib stream, sending each piece of compressed output
+/// prefixed by its length. Returns the total compressed size.
+fn send_compressed(stream: &mut impl Write, data: &[u8]) -> io::Result<usize> {
+    let mut compress = Compress::new(Compression::default(), true);
+    let mut output = vec![0; CHUNK_SIZE];
+    let mut sent = 0;
+
+    // An empty file is still sent as a (finished) zlib stream.
+    let chunk_count = data.len().div_ceil(CHUNK_SIZE).max(1);
+    for index in 0..chunk_count {
+        let chunk = &data[index * CHUNK_SIZE..data.len().min((index + 1) * CHUNK_SIZE)];
+        let last = index + 1 == chunk_count;
+        let flush = if last {
+            FlushCompress::Finish
+        } else {
+            FlushCompress::None
+        };
+
+        let mut input = chunk;
+        loop {
+            let (total_in, total_out) = (compress.total_in(), compress.total_out());
+            let status = compress.compress(input, &mut output, flush)?;
+            let consumed = (compress.total_in() - total_in) as usize;
+            let produced = (compress.total_out() - total_out) as usize;
+            input = &input[consumed..];
+
+            if produced > 0 {
+                write_len(stream, produced)?;
+                stream.write_all(&output[..produced])?;
+                sent += produced;
+            }
+
+            // Keep going while the output buffer fills up, like zlib's deflate.
+            let done = match status {
+                Status::StreamEnd => true,
+                Status::Ok => produced < output.len() && input.is_empty() && !last,
+                Status::BufError => produced == 0,
+            };
+            if done {
+                break;
+            }
+        }
+    }
+
+    Ok(sent)
+}
+
+fn write_len(stream: &mut impl Write, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len).map_err(|_| io::Error::other("data too large to send"))?;
+    stream.write_all(&len.to_le_bytes())
+}
+
+fn read_response(stream: &mut impl Read) -> io::Result<i32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::{Decompress, FlushDecompress};
+
+    fn read_u32(stream: &mut TcpStream) -> u32 {
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).unwrap();
+        u32::from_le_bytes(buf)
+    }
+
+    fn read_bytes(stream: &mut TcpStream, len: u32) -> Vec<u8> {
+        let mut buf = vec![0; len as usize];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    /// Accept one upload like the netloader does, returning the file name,
+    /// the decompressed contents and the arguments.
+    fn receive(listener: TcpListener) -> (String, Vec<u8>, Vec<u8>) {
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let name_len = read_u32(&mut stream);
+        let name = String::from_utf8(read_bytes(&mut stream, name_len)).unwrap();
+        let size = read_u32(&mut stream) as usize;
+        stream.write_all(&0i32.to_le_bytes()).unwrap();
+
+        // Like the netloader, read chunks until the end of the zlib stream.
+        let mut decompress = Decompress::new(true);
+        let mut data = vec![0; size + 1];
+        let mut status = Status::Ok;
+        while status != Status::StreamEnd {
+            let chunk_len = read_u32(&mut stream);
+            assert!(chunk_len as usize <= CHUNK_SIZE);
+            let chunk = read_bytes(&mut stream, chunk_len);
+
+            let mut input = &chunk[..];
+            while !input.is_empty() {
+                let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+                status = decompress
+                    .decompress(
+                        input,
+                        &mut data[total_out as usize..],
+                        FlushDecompress::None,
+                    )
+                    .unwrap();
+                input = &input[(decompress.total_in() - total_in) as usize..];
+            }
+        }
+        assert_eq!(decompress.total_out() as usize, size);
+        data.truncate(size);
+        stream.write_all(&0i32.to_le_bytes()).unwrap();
+
+        let args_len = read_u32(&mut stream);
+        let args = read_bytes(&mut stream, args_len);
+
+        (name, data, args)
+    }
+
+    #[test]
+    fn send_to_netloader() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let device = std::thread::spawn(move || receive(listener));
+
+        // Large enough to need several chunks even when compressed
+        let contents: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| (i * 7919).to_le_bytes())
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.3dsx");
+        std::fs::write(&path, &contents).unwrap();
+
+        let argv = ["3dslink:/app.3dsx", "--flag", "value"].map(String::from);
+        send(addr, &path, &argv).unwrap();
+
+        let (name, data, args) = device.join().unwrap();
+        assert_eq!(name, path.file_name().unwrap().to_str().unwrap());
+        assert!(data == contents);
+        assert_eq!(args, b"3dslink:/app.3dsx\0--flag\0value\0");
+    }
+
+    #[test]
+    fn refused_by_device() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let device = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let name_len = read_u32(&mut stream);
+            read_bytes(&mut stream, name_len + 4);
+            stream.write_all(&(-2i32).to_le_bytes()).unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.3dsx");
+        std::fs::write(&path, b"3DSX").unwrap();
+        let result = send(addr, &path, &[]);
+        device.join().unwrap();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "insufficient space on the device"
+        );
+    }
+
+    #[test]
+    fn server_sessions() {
+        let server =
+            Server::from_listener(TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        server.set_device(Ipv4Addr::LOCALHOST.into());
+
+        // The executable connects, disconnects, then reconnects
+        let device = std::thread::spawn(move || {
+            for message in ["hello\n", "again\n"] {
+                TcpStream::connect(addr)
+                    .unwrap()
+                    .write_all(message.as_bytes())
+                    .unwrap();
+            }
+        });
+
+        for (number, expected) in [(1, "hello\n"), (2, "again\n")] {
+            let mut session = server.accept().unwrap();
+            assert_eq!(session.number(), number);
+            assert_eq!(session.peer_addr().ip(), Ipv4Addr::LOCALHOST);
+
+            let mut output = String::new();
+            session.read_to_string(&mut output).unwrap();
+            assert_eq!(output, expected);
+        }
+
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn accept_timeout() {
+        let server =
+            Server::from_listener(TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let err = server
+            .accept_timeout(Duration::from_millis(100))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let device = std::thread::spawn(move || {
+            TcpStream::connect(addr)
+                .unwrap()
+                .write_all(b"hello\n")
+                .unwrap();
+        });
+        let mut session = server.accept_timeout(Duration::from_secs(10)).unwrap();
+        let mut output = String::new();
+        session.read_to_string(&mut output).unwrap();
+        assert_eq!(output, "hello\n");
+
+        device.join().unwrap();
+    }
+}
@@ -0,0 +1,730 @@
+//! Conversion of ELF executables into the 3DSX format loaded by the Homebrew
+//! Launcher.
+//!
+//! A 3DSX contains the code, read-only data and data segments of the program,
+//! plus relocation tables that let the loader place the segments anywhere in
+//! memory. See <https://www.3dbrew.org/wiki/3DSX_Format>.
+
+use crate::elf::{self, Elf, SHF_ALLOC, SHT_NOBITS};
+use crate::romfs::RomFs;
+
+use core::fmt;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+/// Size of the 3DSX header without the extended header.
+const HEADER_SIZE: u16 = 0x20;
+/// Size of the 3DSX header including the SMDH and RomFS offsets.
+const EXTENDED_HEADER_SIZE: u16 = 0x2C;
+/// Size of the relocation header of each segment.
+const RELOC_HEADER_SIZE: u16 = 8;
+
+pub(crate) const PAGE_SIZE: u32 = 0x1000;
+
+const R_ARM_ABS32: u32 = 2;
+const R_ARM_REL32: u32 = 3;
+const R_ARM_TARGET1: u32 = 38;
+const R_ARM_TARGET2: u32 = 41;
+const R_ARM_PREL31: u32 = 42;
+
+/// The segments of a 3DSX, in the order they are laid out in memory.
+const SEGMENT_NAMES: [&str; 3] = ["code", "rodata", "data"];
+
+/// An executable converted to the 3DSX format.
+pub struct ThreeDsx {
+    segments: [Segment; 3],
+    bss_size: u32,
+}
+
+/// The header of a 3DSX file, with the number of words relocated in each
+/// segment.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Header {
+    pub header_size: u16,
+    pub version: u32,
+    pub flags: u32,
+    /// The sizes of the code, rodata and data segments in the file.
+    pub segment_sizes: [u32; 3],
+    pub bss_size: u32,
+    /// The number of absolute and relative relocated words of each segment.
+    pub relocations: [[u32; 2]; 3],
+    /// The offset and size of the SMDH, if there is one.
+    pub smdh: Option<(u32, u32)>,
+    /// The offset of the RomFS, which ends with the file, if there is one.
+    pub romfs_offset: Option<u32>,
+}
+
+#[derive(Default)]
+struct Segment {
+    data: Vec<u8>,
+    /// Word indices to patch with the absolute address of their target.
+    absolute: BTreeSet<u32>,
+    /// Word indices to patch with the offset of their target relative to
+    /// themselves.
+    relative: BTreeSet<u32>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input is not a valid ARM executable.
+    Elf(elf::ParseError),
+    /// The program segments are not laid out the way the 3DSX loader expects.
+    Layout(String),
+    /// A relocation cannot be represented in the 3DSX format.
+    UnsupportedRelocation {
+        kind: u32,
+        address: u32,
+        section: String,
+    },
+}
+
+impl ThreeDsx {
+    /// Convert an ARM ELF executable, which must have been linked with
+    /// `--emit-relocs` so the relocations are available.
+    pub fn from_elf(data: &[u8]) -> Result<Self, Error> {
+        let elf = Elf::parse(data)?;
+
+        let loaded = elf.program_segments()?;
+
+        let base = loaded[0]
+            .ok_or_else(|| Error::Layout(String::from("no code segment")))?
+            .vaddr;
+        if base % PAGE_SIZE != 0 {
+            return Err(Error::Layout(format!(
+                "code segment at {base:#010x} is not page-aligned"
+            )));
+        }
+
+        // The loader places each segment on its own pages, so the segments
+        // must follow each other on page boundaries in the ELF as well.
+        let mut segments: [Segment; 3] = Default::default();
+        let mut bss_size = 0;
+        let mut starts = [base; 3];
+        let mut address = base;
+        for (index, segment) in loaded.iter().enumerate() {
+            starts[index] = address;
+            let Some(segment) = segment else { continue };
+
+            if segment.vaddr != address {
+                return Err(Error::Layout(format!(
+                    "{} segment is at {:#010x}, expected {address:#010x}",
+                    SEGMENT_NAMES[index], segment.vaddr
+                )));
+            }
+
+            let data = &mut segments[index].data;
+            data.extend_from_slice(segment.data);
+            if index == 2 {
+                data.resize(align(data.len() as u32, 4) as usize, 0);
+                bss_size = segment.mem_size.saturating_sub(data.len() as u32);
+                address += segment.mem_size;
+            } else {
+                data.resize(align(segment.mem_size, PAGE_SIZE) as usize, 0);
+                address += data.len() as u32;
+            }
+        }
+        let top = address;
+
+        // The data segment extends into the BSS, which is not in the file.
+        let ends = [
+            starts[0] + segments[0].data.len() as u32,
+            starts[1] + segments[1].data.len() as u32,
+            top,
+        ];
+        let segment_of = |address: u32| (0..3).find(|&i| address >= starts[i] && address < ends[i]);
+
+        for section in &elf.sections {
+            let Some(target) = elf.sections.get(section.info as usize) else {
+                continue;
+            };
+            if target.flags & SHF_ALLOC == 0 || target.kind == SHT_NOBITS {
+                continue;
+            }
+
+            for relocation in elf.relocations(section)? {
+                let unsupported = || Error::UnsupportedRelocation {
+                    kind: relocation.kind,
+                    address: relocation.offset,
+                    section: target.name.clone(),
+                };
+
+                let absolute = match relocation.kind {
+                    R_ARM_ABS32 | R_ARM_TARGET1 => true,
+                    R_ARM_REL32 | R_ARM_TARGET2 | R_ARM_PREL31 => false,
+                    kind if is_position_independent(kind) => continue,
+                    _ => return Err(unsupported()),
+                };
+
+                let source = relocation.offset;
+                let index = segment_of(source).ok_or_else(unsupported)?;
+                let word_index = (source - starts[index]) / 4;
+                let Some(word) = segments[index]
+                    .data
+                    .get_mut(word_index as usize * 4..)
+                    .and_then(|data| data.get_mut(..4))
+                    .filter(|_| source % 4 == 0)
+                else {
+                    return Err(unsupported());
+                };
+                let value = u32::from_le_bytes(word.try_into().unwrap());
+
+                let destination = if absolute {
+                    value
+                } else if relocation.kind == R_ARM_PREL31 {
+                    // Sign-extend the 31-bit offset
+                    source.wrapping_add(((value << 1) as i32 >> 1) as u32)
+                } else {
+                    source.wrapping_add(value)
+                };
+
+                // References outside of the program (e.g. to undefined weak
+                // symbols) and relative references within a segment are left
+                // as they are.
+                if destination < base || destination > top {
+                    continue;
+                }
+                if !absolute && segment_of(destination) == Some(index) {
+                    continue;
+                }
+
+                // The loader expects patched words to hold offsets from the
+                // start of the program. Like with 3dsxtool, the top bit of a
+                // 31-bit offset is kept, as it is not part of the offset.
+                let mut offset = destination - base;
+                if relocation.kind == R_ARM_PREL31 {
+                    offset = offset & 0x7FFF_FFFF | value & 0x8000_0000;
+                }
+                word.copy_from_slice(&offset.to_le_bytes());
+                let segment = &mut segments[index];
+                if absolute {
+                    segment.absolute.insert(word_index);
+                } else {
+                    segment.relative.insert(word_index);
+                }
+            }
+        }
+
+        Ok(Self { segments, bss_size })
+    }
+
+    /// Write the 3DSX, optionally embedding an SMDH and a RomFS image.
+    pub fn write_to(
+        &self,
+        mut out: impl Write,
+        smdh: Option<&[u8]>,
+        romfs: Option<&RomFs>,
+    ) -> io::Result<()> {
+        let extended = smdh.is_some() || romfs.is_some();
+        let header_size = if extended {
+            EXTENDED_HEADER_SIZE
+        } else {
+            HEADER_SIZE
+        };
+
+        let relocations = self
+            .segments
+            .iter()
+            .map(|segment| {
+                [
+                    encode_relocations(&segment.absolute),
+                    encode_relocations(&segment.relative),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let [code, rodata, data] = &self.segments;
+        out.write_all(b"3DSX")?;
+        out.write_all(&header_size.to_le_bytes())?;
+        out.write_all(&RELOC_HEADER_SIZE.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?; // format version
+        out.write_all(&0u32.to_le_bytes())?; // flags
+        out.write_all(&(code.data.len() as u32).to_le_bytes())?;
+        out.write_all(&(rodata.data.len() as u32).to_le_bytes())?;
+        out.write_all(&(data.data.len() as u32 + self.bss_size).to_le_bytes())?;
+        out.write_all(&self.bss_size.to_le_bytes())?;
+
+        if extended {
+            let mut offset = u32::from(header_size)
+                + u32::from(RELOC_HEADER_SIZE) * 3
+                + self
+                    .segments
+                    .iter()
+                    .map(|segment| segment.data.len() as u32)
+                    .sum::<u32>()
+                + relocations
+                    .iter()
+                    .flatten()
+                    .map(|r| r.len() as u32 * 4)
+                    .sum::<u32>();
+
+            let smdh_size = smdh.map_or(0, |smdh| smdh.len() as u32);
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(&smdh_size.to_le_bytes())?;
+
+            offset += smdh_size;
+            let romfs_offset = if romfs.is_some() { offset } else { 0 };
+            out.write_all(&romfs_offset.to_le_bytes())?;
+        }
+
+        for [absolute, relative] in &relocations {
+            out.write_all(&(absolute.len() as u32).to_le_bytes())?;
+            out.write_all(&(relative.len() as u32).to_le_bytes())?;
+        }
+
+        for segment in &self.segments {
+            out.write_all(&segment.data)?;
+        }
+
+        for &(skip, patch) in relocations.iter().flatten().flatten() {
+            out.write_all(&skip.to_le_bytes())?;
+            out.write_all(&patch.to_le_bytes())?;
+        }
+
+        if let Some(smdh) = smdh {
+            out.write_all(smdh)?;
+        }
+        if let Some(romfs) = romfs {
+            romfs.write_to(&mut out)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Header {
+    /// Read the header of a 3DSX file, or `None` if the data is not one.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let word = |offset: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                data.get(offset..offset + 4)?.try_into().unwrap(),
+            ))
+        };
+        let half = |offset: usize| -> Option<u16> {
+            Some(u16::from_le_bytes(
+                data.get(offset..offset + 2)?.try_into().unwrap(),
+            ))
+        };
+
+        if data.get(..4)? != b"3DSX" {
+            return None;
+        }
+        let header_size = half(4)?;
+        let reloc_header_size = half(6)?;
+
+        let bss_size = word(0x1C)?;
+        let segment_sizes = [word(0x10)?, word(0x14)?, word(0x18)?.checked_sub(bss_size)?];
+        let (smdh, romfs_offset) = if header_size >= EXTENDED_HEADER_SIZE {
+            let smdh_size = word(0x24)?;
+            let romfs_offset = word(0x28)?;
+            (
+                (smdh_size != 0).then_some((word(0x20)?, smdh_size)),
+                (romfs_offset != 0).then_some(romfs_offset),
+            )
+        } else {
+            (None, None)
+        };
+
+        // The relocation tables follow the segments, in the same order as
+        // their headers.
+        let mut table = usize::from(header_size)
+            + usize::from(reloc_header_size) * 3
+            + segment_sizes
+                .iter()
+                .map(|&size| size as usize)
+                .sum::<usize>();
+        let mut relocations = [[0; 2]; 3];
+        for (segment, counts) in relocations.iter_mut().enumerate() {
+            let header = usize::from(header_size) + usize::from(reloc_header_size) * segment;
+            for (kind, count) in counts.iter_mut().enumerate() {
+                for _ in 0..word(header + kind * 4)? {
+                    *count += u32::from(half(table + 2)?);
+                    table += 4;
+                }
+            }
+        }
+
+        Some(Self {
+            header_size,
+            version: word(8)?,
+            flags: word(0xC)?,
+            segment_sizes,
+            bss_size,
+            relocations,
+            smdh,
+            romfs_offset,
+        })
+    }
+}
+
+/// Encode a set of word indices as a list of `(skip, patch)` pairs: skip
+/// ahead a number of words, then patch a number of consecutive words.
+fn encode_relocations(words: &BTreeSet<u32>) -> Vec<(u16, u16)> {
+    let mut entries = Vec::new();
+    let mut position = 0;
+    let mut words = words.iter().copied().peekable();
+
+    while let Some(start) = words.next() {
+        let mut end = start + 1;
+        while words.next_if_eq(&end).is_some() {
+            end += 1;
+        }
+
+        let mut skip = start - position;
+        while skip > u32::from(u16::MAX) {
+            entries.push((u16::MAX, 0));
+            skip -= u32::from(u16::MAX);
+        }
+
+        let mut patch = end - start;
+        while patch > u32::from(u16::MAX) {
+            entries.push((skip as u16, u16::MAX));
+            skip = 0;
+            patch -= u32::from(u16::MAX);
+        }
+        entries.push((skip as u16, patch as u16));
+
+        position = end;
+    }
+
+    entries
+}
+
+/// Whether a relocation type only refers to its target relative to the
+/// program counter (or thread pointer), so it stays valid as long as the
+/// segment is moved as a whole.
+fn is_position_independent(kind: u32) -> bool {
+    matches!(
+        kind,
+        0 // R_ARM_NONE
+        | 1 // R_ARM_PC24
+        | 4 // R_ARM_LDR_PC_G0
+        | 10 // R_ARM_THM_CALL
+        | 11 // R_ARM_THM_PC8
+        | 28 // R_ARM_CALL
+        | 29 // R_ARM_JUMP24
+        | 30 // R_ARM_THM_JUMP24
+        | 32 // R_ARM_TLS_LE32
+        | 40 // R_ARM_V4BX
+        | 45 // R_ARM_MOVW_PREL_NC
+        | 46 // R_ARM_MOVT_PREL
+        | 49 // R_ARM_THM_MOVW_PREL_NC
+        | 50 // R_ARM_THM_MOVT_PREL
+        | 51 // R_ARM_THM_JUMP19
+        | 52 // R_ARM_THM_JUMP6
+        | 53 // R_ARM_THM_ALU_PREL_11_0
+        | 54 // R_ARM_THM_PC12
+        | 57
+            ..=69 // R_ARM_ALU_PC_G0_NC ..= R_ARM_LDC_PC_G2
+        | 102 // R_ARM_THM_JUMP11
+        | 103 // R_ARM_THM_JUMP8
+    )
+}
+
+/// The name of an ARM relocation type, for error messages.
+fn relocation_name(kind: u32) -> Option<&'static str> {
+    Some(match kind {
+        2 => "R_ARM_ABS32",
+        3 => "R_ARM_REL32",
+        5 => "R_ARM_ABS16",
+        6 => "R_ARM_ABS12",
+        7 => "R_ARM_THM_ABS5",
+        8 => "R_ARM_ABS8",
+        9 => "R_ARM_SBREL32",
+        26 => "R_ARM_GOT_BREL",
+        38 => "R_ARM_TARGET1",
+        39 => "R_ARM_SBREL31",
+        41 => "R_ARM_TARGET2",
+        42 => "R_ARM_PREL31",
+        43 => "R_ARM_MOVW_ABS_NC",
+        44 => "R_ARM_MOVT_ABS",
+        47 => "R_ARM_THM_MOVW_ABS_NC",
+        48 => "R_ARM_THM_MOVT_ABS",
+        55 => "R_ARM_ABS32_NOI",
+        96 => "R_ARM_GOT_PREL",
+        104 => "R_ARM_TLS_GD32",
+        107 => "R_ARM_TLS_IE32",
+        _ => return None,
+    })
+}
+
+pub(crate) fn align(value: u32, alignment: u32) -> u32 {
+    (value + alignment - 1) & !(alignment - 1)
+}
+
+impl From<elf::ParseError> for Error {
+    fn from(err: elf::ParseError) -> Self {
+        Self::Elf(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Elf(err) => write!(f, "{err}"),
+            Self::Layout(msg) => write!(f, "unsupported segment layout: {msg}"),
+            Self::UnsupportedRelocation {
+                kind,
+                address,
+                section,
+            } => {
+                write!(f, "unsupported relocation ")?;
+                match relocation_name(*kind) {
+                    Some(name) => write!(f, "{name}")?,
+                    None => write!(f, "type {kind}")?,
+                }
+                write!(f, " at {address:#010x} in section `{section}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use crate::elf::{PF_R, PF_W, PF_X, PT_LOAD};
+
+    const BASE: u32 = 0x100000;
+
+    /// Build a minimal executable with the given segment contents and
+    /// relocations (applied to the code segment).
+    pub(crate) fn build_elf(
+        code: &[u8],
+        rodata: &[u8],
+        data: &[u8],
+        relocations: &[(u32, u32)],
+    ) -> Vec<u8> {
+        let rodata_addr = BASE + align(code.len() as u32, PAGE_SIZE);
+        let data_addr = rodata_addr + align(rodata.len() as u32, PAGE_SIZE);
+
+        let mut out = vec![0; 0x34];
+        out[..6].copy_from_slice(b"\x7FELF\x01\x01");
+        let put = |out: &mut Vec<u8>, offset: usize, bytes: &[u8]| {
+            out[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(&mut out, 0x10, &2u16.to_le_bytes());
+        put(&mut out, 0x12, &40u16.to_le_bytes());
+        put(&mut out, 0x18, &BASE.to_le_bytes());
+        put(&mut out, 0x1C, &0x34u32.to_le_bytes());
+        put(&mut out, 0x2A, &32u16.to_le_bytes());
+        put(&mut out, 0x2C, &3u16.to_le_bytes());
+        put(&mut out, 0x2E, &40u16.to_le_bytes());
+        put(&mut out, 0x30, &4u16.to_le_bytes());
+        put(&mut out, 0x32, &3u16.to_le_bytes());
+
+        let contents_offset = 0x34 + 3 * 32;
+        let mut contents = Vec::new();
+        for (bytes, vaddr, mem_size, flags) in [
+            (code, BASE, code.len() as u32, PF_R | PF_X),
+            (rodata, rodata_addr, rodata.len() as u32, PF_R),
+            (data, data_addr, data.len() as u32 + 0x10, PF_R | PF_W),
+        ] {
+            let offset = contents_offset + contents.len() as u32;
+            for word in [
+                PT_LOAD,
+                offset,
+                vaddr,
+                vaddr,
+                bytes.len() as u32,
+                mem_size,
+                flags,
+                PAGE_SIZE,
+            ] {
+                out.extend(word.to_le_bytes());
+            }
+            contents.extend(bytes);
+        }
+        out.extend(&contents);
+
+        let rel_offset = out.len() as u32;
+        for &(offset, kind) in relocations {
+            out.extend((BASE + offset).to_le_bytes());
+            out.extend(kind.to_le_bytes());
+        }
+
+        let strtab_offset = out.len() as u32;
+        out.extend(b"\0.text\0.rel.text\0.shstrtab\0");
+        let strtab_size = out.len() as u32 - strtab_offset;
+
+        let shoff = out.len() as u32;
+        put(&mut out, 0x20, &shoff.to_le_bytes());
+        for header in [
+            [0; 10],
+            [
+                1,
+                1,
+                0x6,
+                BASE,
+                contents_offset,
+                code.len() as u32,
+                0,
+                0,
+                4,
+                0,
+            ],
+            [
+                7,
+                elf::SHT_REL,
+                0,
+                0,
+                rel_offset,
+                relocations.len() as u32 * 8,
+                0,
+                1,
+                4,
+                8,
+            ],
+            [17, 3, 0, 0, strtab_offset, strtab_size, 0, 0, 1, 0],
+        ] {
+            for word in header {
+                out.extend(word.to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn relocation_runs() {
+        let words = BTreeSet::from([1, 2, 3, 5, 0x2_0005, 0x2_0006]);
+        assert_eq!(
+            encode_relocations(&words),
+            vec![(1, 3), (1, 1), (0xFFFF, 0), (0xFFFF, 0), (1, 2)]
+        );
+    }
+
+    #[test]
+    fn convert_elf() {
+        let rodata_addr = BASE + PAGE_SIZE;
+        let data_addr = BASE + 2 * PAGE_SIZE;
+        let code = words(&[
+            0xE12F_FF1E,                        // bx lr
+            rodata_addr + 4,                    // absolute pointer to rodata
+            data_addr - (BASE + 8),             // relative pointer to data
+            0xEB00_0000,                        // bl (position independent)
+            0,                                  // pointer to an undefined weak symbol
+            (BASE + 4).wrapping_sub(BASE + 20), // relative pointer within the code segment
+        ]);
+        let elf = build_elf(
+            &code,
+            b"rodata!!",
+            b"data",
+            &[
+                (4, R_ARM_ABS32),
+                (8, R_ARM_REL32),
+                (12, 28),
+                (16, R_ARM_ABS32),
+                (20, R_ARM_REL32),
+            ],
+        );
+
+        let mut out = Vec::new();
+        ThreeDsx::from_elf(&elf)
+            .unwrap()
+            .write_to(&mut out, Some(&[0xAA; 4]), None)
+            .unwrap();
+
+        let header = words(&[
+            0x1000,
+            0x1000,
+            4 + 0x10,
+            0x10,
+            0x2C + 0x18 + 0x2004 + 8,
+            4,
+            0,
+        ]);
+        assert_eq!(&out[..4], b"3DSX");
+        assert_eq!(&out[4..8], &[0x2C, 0, 8, 0]);
+        assert_eq!(&out[0x10..0x2C], &header[..]);
+        assert_eq!(&out[0x2C..0x44], &words(&[1, 1, 0, 0, 0, 0])[..]);
+
+        let code_out = &out[0x44..0x44 + 0x1000];
+        assert_eq!(
+            &code_out[..24],
+            &words(&[0xE12F_FF1E, 0x1004, 0x2000, 0xEB00_0000, 0, 0xFFFF_FFF0])[..]
+        );
+        assert_eq!(&out[0x1044..0x104C], b"rodata!!");
+        assert_eq!(&out[0x2044..0x2048], b"data");
+
+        // code: absolute (skip 1, patch 1), relative (skip 2, patch 1)
+        assert_eq!(&out[0x2048..0x2050], &[1, 0, 1, 0, 2, 0, 1, 0]);
+        assert_eq!(&out[0x2050..], &[0xAA; 4]);
+    }
+
+    #[test]
+    fn prel31_relocation() {
+        let data_addr = BASE + 2 * PAGE_SIZE;
+        let code = words(&[
+            0xE12F_FF1E,
+            0x8000_0000 | (data_addr - (BASE + 4)),
+            (data_addr - (BASE + 8)) & 0x7FFF_FFFF,
+        ]);
+        let elf = build_elf(
+            &code,
+            b"rodata!!",
+            b"data",
+            &[(4, R_ARM_PREL31), (8, R_ARM_PREL31)],
+        );
+
+        let mut out = Vec::new();
+        ThreeDsx::from_elf(&elf)
+            .unwrap()
+            .write_to(&mut out, None, None)
+            .unwrap();
+
+        let code_out = &out[usize::from(HEADER_SIZE) + 0x18..];
+        assert_eq!(
+            &code_out[..12],
+            &words(&[0xE12F_FF1E, 0x8000_2000, 0x2000])[..]
+        );
+    }
+
+    #[test]
+    fn read_header() {
+        let code = words(&[BASE + 8, BASE + 12, 0, 0]);
+        let elf = build_elf(
+            &code,
+            b"rodata",
+            b"data",
+            &[(0, R_ARM_ABS32), (4, R_ARM_ABS32)],
+        );
+        let mut out = Vec::new();
+        ThreeDsx::from_elf(&elf)
+            .unwrap()
+            .write_to(&mut out, Some(&[0xAA; 4]), None)
+            .unwrap();
+
+        assert_eq!(
+            Header::parse(&out),
+            Some(Header {
+                header_size: 0x2C,
+                version: 0,
+                flags: 0,
+                segment_sizes: [0x1000, 0x1000, 4],
+                bss_size: 0x10,
+                relocations: [[2, 0], [0, 0], [0, 0]],
+                smdh: Some((0x2C + 0x18 + 0x2004 + 4, 4)),
+                romfs_offset: None,
+            })
+        );
+        assert_eq!(Header::parse(&out[..0x30]), None);
+        assert_eq!(Header::parse(b"3DSY"), None);
+    }
+
+    #[test]
+    fn unsupported_relocation() {
+        let elf = build_elf(&[0; 8], &[], &[], &[(4, 43)]);
+
+        let err = ThreeDsx::from_elf(&elf).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unsupported relocation R_ARM_MOVW_ABS_NC at 0x00100004 in section `.text`"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+//! Watching the sources and assets of the workspace, to rebuild executables
+//! when they change.
+
+use crate::{get_romfs_path, CTRConfig, Error};
+
+use cargo_metadata::MetadataCommand;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How long to wait for more changes after one, e.g. while an editor saves
+/// several files, before rebuilding.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Watches files and directories recursively, and waits for them to change.
+pub struct Watcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Directories watched recursively.
+    watched: Vec<PathBuf>,
+    /// Directories whose changes are ignored, e.g. the target directory.
+    ignored: Vec<PathBuf>,
+}
+
+impl Watcher {
+    /// Watch the directories of the workspace members, except the target
+    /// directory.
+    pub fn for_workspace() -> Result<Self, Error> {
+        let metadata = MetadataCommand::new().no_deps().exec()?;
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = Self {
+            watcher: notify::recommended_watcher(sender)?,
+            events,
+            watched: Vec::new(),
+            ignored: vec![metadata.target_directory.into_std_path_buf()],
+        };
+
+        for package in metadata.packages {
+            if let Some(dir) = package.manifest_path.parent() {
+                watcher.watch(dir.as_std_path())?;
+            }
+        }
+
+        Ok(watcher)
+    }
+
+    /// Also watch the assets of the executables, e.g. a `RomFS` directory
+    /// outside of their package.
+    pub fn watch_assets(&mut self, configs: &[CTRConfig]) -> Result<(), Error> {
+        for config in configs {
+            if let Some(romfs_dir) = get_romfs_path(config)? {
+                self.watch(&romfs_dir)?;
+            }
+            self.watch(&config.icon)?;
+        }
+
+        Ok(())
+    }
+
+    /// Watch a file or directory, unless it is already watched.
+    fn watch(&mut self, path: &Path) -> Result<(), Error> {
+        if !path.exists() || self.watched.iter().any(|dir| path.starts_with(dir)) {
+            return Ok(());
+        }
+
+        self.watcher.watch(path, RecursiveMode::Recursive)?;
+        self.watched.push(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Wait until something changes, and for the changes to settle. Returns a
+    /// path that changed.
+    pub fn wait_for_change(&self) -> Result<PathBuf, Error> {
+        let changed = loop {
+            if let Some(path) = self.changed_path(self.events.recv())? {
+                break path;
+            }
+        };
+
+        loop {
+            match self.events.recv_timeout(DEBOUNCE) {
+                Ok(event) => {
+                    event?;
+                }
+                Err(RecvTimeoutError::Timeout) => return Ok(changed),
+                Err(RecvTimeoutError::Disconnected) => return Err(disconnected()),
+            }
+        }
+    }
+
+    /// The first path changed by an event, unless it is ignored.
+    fn changed_path(
+        &self,
+        event: Result<notify::Result<Event>, mpsc::RecvError>,
+    ) -> Result<Option<PathBuf>, Error> {
+        let event = event.map_err(|_| disconnected())??;
+
+        Ok(event
+            .paths
+            .into_iter()
+            .find(|path| is_change(&event.kind) && !self.is_ignored(path)))
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignored.iter().any(|dir| path.starts_with(dir))
+            || path
+                .components()
+                .any(|component| component.as_os_str() == ".git")
+    }
+}
+
+/// Whether the event changes the contents of files, rather than e.g. their
+/// access time.
+fn is_change(kind: &EventKind) -> bool {
+    match kind {
+        EventKind::Create(_) | EventKind::Remove(_) => true,
+        EventKind::Modify(modify) => !matches!(modify, ModifyKind::Metadata(_)),
+        _ => false,
+    }
+}
+
+fn disconnected() -> Error {
+    Error::Watch(notify::Error::generic("the file watcher stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind};
+
+    #[test]
+    fn changes() {
+        assert!(is_change(&EventKind::Create(CreateKind::File)));
+        assert!(is_change(&EventKind::Modify(ModifyKind::Data(
+            DataChange::Content
+        ))));
+        assert!(!is_change(&EventKind::Modify(ModifyKind::Metadata(
+            MetadataKind::AccessTime
+        ))));
+        assert!(!is_change(&EventKind::Access(AccessKind::Read)));
+    }
+
+    #[test]
+    fn debounce() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+
+        let (sender, events) = mpsc::channel();
+        let watcher = Watcher {
+            watcher: notify::recommended_watcher(sender.clone()).unwrap(),
+            events,
+            watched: Vec::new(),
+            ignored: vec![dir.join("target")],
+        };
+
+        let event =
+            |path: PathBuf| Ok(Event::new(EventKind::Create(CreateKind::File)).add_path(path));
+        sender.send(event(dir.join("target/app.elf"))).unwrap();
+        sender.send(event(dir.join(".git/index"))).unwrap();
+        sender.send(event(dir.join("src/main.rs"))).unwrap();
+        sender.send(event(dir.join("src/lib.rs"))).unwrap();
+
+        let changed = watcher.wait_for_change().unwrap();
+
+        assert_eq!(changed, dir.join("src/main.rs"));
+        assert!(watcher.events.try_recv().is_err());
+    }
+}
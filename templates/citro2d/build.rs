@@ -0,0 +1,11 @@
+use std::env;
+
+fn main() {
+    let devkitpro =
+        env::var("DEVKITPRO").expect("DEVKITPRO is not defined as an environment variable");
+
+    println!("cargo:rerun-if-env-changed=DEVKITPRO");
+    println!("cargo:rustc-link-search=native={devkitpro}/libctru/lib");
+    println!("cargo:rustc-link-lib=static=citro2d");
+    println!("cargo:rustc-link-lib=static=citro3d");
+}
@@ -0,0 +1,107 @@
+use ctru::prelude::*;
+
+use citro2d::*;
+
+fn main() {
+    ctru::use_panic_handler();
+
+    let _gfx = Gfx::new().expect("Couldn't obtain GFX controller");
+    let mut hid = Hid::new().expect("Couldn't obtain HID controller");
+    let apt = Apt::new().expect("Couldn't obtain APT controller");
+
+    let top = unsafe {
+        assert!(C3D_Init(C3D_DEFAULT_CMDBUF_SIZE));
+        assert!(C2D_Init(C2D_DEFAULT_MAX_OBJECTS));
+        C2D_Prepare();
+        C2D_CreateScreenTarget(GFX_TOP, GFX_LEFT)
+    };
+
+    let mut x = 0.0;
+    while apt.main_loop() {
+        hid.scan_input();
+        if hid.keys_down().contains(KeyPad::START) {
+            break;
+        }
+
+        x = (x + 2.0) % TOP_SCREEN_WIDTH;
+
+        unsafe {
+            C3D_FrameBegin(C3D_FRAME_SYNCDRAW);
+            C2D_TargetClear(top, color32(0x20, 0x20, 0x40, 0xFF));
+            scene_begin(top, TOP_SCREEN_WIDTH);
+            C2D_DrawRectangle(
+                x,
+                95.0,
+                0.0,
+                50.0,
+                50.0,
+                color32(0xFF, 0x00, 0x00, 0xFF),
+                color32(0x00, 0xFF, 0x00, 0xFF),
+                color32(0x00, 0x00, 0xFF, 0xFF),
+                color32(0xFF, 0xFF, 0xFF, 0xFF),
+            );
+            C3D_FrameEnd(0);
+        }
+    }
+
+    unsafe {
+        C2D_Fini();
+        C3D_Fini();
+    }
+}
+
+/// Bindings to the parts of citro2d and citro3d used above. Many citro2d
+/// functions are `static inline` in its headers, so those are written here.
+mod citro2d {
+    use core::ffi::c_void;
+
+    pub type C3D_RenderTarget = c_void;
+
+    pub const C3D_DEFAULT_CMDBUF_SIZE: usize = 0x40000;
+    pub const C2D_DEFAULT_MAX_OBJECTS: usize = 4096;
+    pub const C3D_FRAME_SYNCDRAW: u8 = 1;
+    pub const GFX_TOP: u32 = 0;
+    pub const GFX_LEFT: u32 = 0;
+    pub const TOP_SCREEN_WIDTH: f32 = 400.0;
+    const SCREEN_HEIGHT: u32 = 240;
+
+    extern "C" {
+        pub fn C3D_Init(cmd_buf_size: usize) -> bool;
+        pub fn C3D_Fini();
+        pub fn C3D_FrameBegin(flags: u8) -> bool;
+        pub fn C3D_FrameDrawOn(target: *mut C3D_RenderTarget) -> bool;
+        pub fn C3D_FrameEnd(flags: u8);
+
+        pub fn C2D_Init(max_objects: usize) -> bool;
+        pub fn C2D_Fini();
+        pub fn C2D_Prepare();
+        pub fn C2D_Flush();
+        pub fn C2D_SceneSize(width: u32, height: u32, tilt: bool);
+        pub fn C2D_CreateScreenTarget(screen: u32, side: u32) -> *mut C3D_RenderTarget;
+        pub fn C2D_TargetClear(target: *mut C3D_RenderTarget, color: u32);
+        #[allow(clippy::too_many_arguments)]
+        pub fn C2D_DrawRectangle(
+            x: f32,
+            y: f32,
+            z: f32,
+            w: f32,
+            h: f32,
+            clr0: u32,
+            clr1: u32,
+            clr2: u32,
+            clr3: u32,
+        ) -> bool;
+    }
+
+    /// `C2D_Color32`
+    pub const fn color32(r: u8, g: u8, b: u8, a: u8) -> u32 {
+        u32::from_le_bytes([r, g, b, a])
+    }
+
+    /// `C2D_SceneBegin`, for a screen target (whose framebuffer is rotated).
+    pub unsafe fn scene_begin(target: *mut C3D_RenderTarget, width: f32) {
+        C2D_Flush();
+        C3D_FrameDrawOn(target);
+        C2D_SceneSize(SCREEN_HEIGHT, width as u32, true);
+    }
+}
@@ -0,0 +1,19 @@
+//! Tests of this library run on the 3DS with `cargo 3ds test --lib`, which sends
+//! them to the device and prints their results.
+
+#![cfg_attr(test, feature(custom_test_frameworks))]
+#![cfg_attr(test, test_runner(test_runner::run_3dslink))]
+
+pub fn add(left: usize, right: usize) -> usize {
+    left + right
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(add(2, 2), 4);
+    }
+}